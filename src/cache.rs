@@ -0,0 +1,245 @@
+//! An on-disk cache of the HTML pages `fetch_html` fetches, so that a run
+//! retrying a failed chapter a minute after the last one does not ask the site
+//! for the comic page and every chapter page all over again.
+//!
+//! Each page is kept in a file of its own under the platform's cache
+//! directory, with the time it was fetched and the validators the server sent.
+//! Only a page that parsed is kept, and never the adult gate, so that a page
+//! the site answered wrongly is asked for again on the next run; the cookies
+//! it was asked for with are part of what it is kept under, as the gate's
+//! confirmation and a login change what the site answers.
+//! A page younger than `--cache-ttl` is used as it is; an older one is asked
+//! for again with `If-None-Match` and `If-Modified-Since`, so that a server
+//! that supports them can answer 304 instead of resending it.
+
+use crate::transport::Request;
+use reqwest::header::{HeaderMap, HeaderName, COOKIE, ETAG, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Minutes a page is used without asking the site unless `--cache-ttl` says
+/// otherwise.
+const DEFAULT_TTL_MINUTES: u64 = 30;
+
+/// How long a chapter page is used for at most, whatever `--cache-ttl` says.
+/// The page signs its image URLs with an expiry time, and a page kept past it
+/// would have every image request refused.
+const CHAPTER_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Entries untouched for this long are removed when the cache is opened. Past
+/// the TTL an entry is only good for revalidation, and a page not asked for in
+/// a month is not likely to be asked for again.
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Whether and for how long fetched pages are kept.
+#[derive(Clone, Debug, clap::Args)]
+#[clap(next_help_heading = "CACHE")]
+pub(crate) struct CacheOptions {
+    /// Minutes a cached comic or listing page is used without asking the site;
+    /// chapter pages are kept for five at most
+    #[clap(long, value_name = "MINUTES", default_value_t = DEFAULT_TTL_MINUTES)]
+    pub(crate) cache_ttl: u64,
+    /// Fetch every page afresh and keep it for later runs
+    #[clap(long, conflicts_with = "no-cache")]
+    pub(crate) refresh: bool,
+    /// Neither use nor keep cached pages
+    #[clap(long)]
+    pub(crate) no_cache: bool,
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        CacheOptions { cache_ttl: DEFAULT_TTL_MINUTES, refresh: false, no_cache: false }
+    }
+}
+
+/// How long a kind of page stays fresh in the cache.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Lifetime {
+    /// Comic and listing pages: `--cache-ttl`.
+    Page,
+    /// Chapter pages: `--cache-ttl`, but no more than `CHAPTER_TTL`. Never
+    /// revalidated either, as a 304 would only confirm the expired signatures.
+    Chapter,
+}
+
+/// `platform cache directory/mhg_dl_rs/html`, if there is one.
+fn cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("mhg_dl_rs").join("html"))
+}
+
+/// A page as it was last fetched.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct Entry {
+    /// Checked on lookup, so that two URLs hashing to the same file name do
+    /// not answer for each other.
+    url: String,
+    /// A hash of the cookies the page was asked for with, checked likewise.
+    cookies: u64,
+    /// Seconds since the epoch.
+    fetched: u64,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: String,
+}
+
+impl Entry {
+    pub(crate) fn body(&self) -> &str {
+        &self.body
+    }
+
+    /// Make `request` conditional on the page having changed since this entry
+    /// was fetched.
+    pub(crate) fn revalidate(&self, request: Request) -> Request {
+        let request = match &self.etag {
+            Some(etag) => request.header("if-none-match", etag.as_str()),
+            None => request,
+        };
+        match &self.last_modified {
+            Some(at) => request.header("if-modified-since", at.as_str()),
+            None => request,
+        }
+    }
+}
+
+/// What the cache has for a URL.
+pub(crate) enum Cached {
+    /// Young enough to use without asking.
+    Fresh(String),
+    /// Too old to use as it is, but the server can say whether it changed.
+    Stale(Entry),
+}
+
+/// The cache `fetch_html` reads from and writes to. The default one, as in
+/// tests, has no directory and keeps nothing.
+#[derive(Default)]
+pub(crate) struct HtmlCache {
+    dir: Option<PathBuf>,
+    /// Whether lookups may be answered; `--refresh` only writes.
+    read: bool,
+    ttl: Duration,
+}
+
+impl HtmlCache {
+    /// The cache `opts` asks for, in the platform's cache directory.
+    pub(crate) fn open(opts: &CacheOptions) -> Self {
+        if opts.no_cache {
+            return HtmlCache::default();
+        }
+        let cache = HtmlCache::in_dir(cache_path(), opts);
+        cache.prune();
+        cache
+    }
+
+    /// The cache `opts` asks for, kept in `dir`.
+    pub(crate) fn in_dir(dir: Option<PathBuf>, opts: &CacheOptions) -> Self {
+        HtmlCache {
+            dir,
+            read: !opts.refresh,
+            ttl: Duration::from_secs(opts.cache_ttl.saturating_mul(60)),
+        }
+    }
+
+    /// What is kept for `request`, if anything worth having. An entry that
+    /// does not read is a miss: it is overwritten by the next fetch anyway.
+    pub(crate) fn lookup(&self, request: &Request, lifetime: Lifetime) -> Option<Cached> {
+        if !self.read {
+            return None;
+        }
+        let (url, cookies) = (request.url.as_str(), cookie_hash(request));
+        let text = fs::read_to_string(self.entry_path(url, cookies)?).ok()?;
+        let entry: Entry =
+            serde_json::from_str(&text).ok().filter(|e: &Entry| e.url == url && e.cookies == cookies)?;
+        let ttl = match lifetime {
+            Lifetime::Page => self.ttl,
+            Lifetime::Chapter => self.ttl.min(CHAPTER_TTL),
+        };
+        let age = Duration::from_secs(unix_now().saturating_sub(entry.fetched));
+        if age < ttl {
+            Some(Cached::Fresh(entry.body))
+        } else if lifetime == Lifetime::Page && (entry.etag.is_some() || entry.last_modified.is_some()) {
+            Some(Cached::Stale(entry))
+        } else {
+            None
+        }
+    }
+
+    /// Keep `body`, the answer to `request` sent with `headers`, for later
+    /// lookups.
+    pub(crate) fn store(&self, request: &Request, headers: &HeaderMap, body: &str) {
+        let value = |name: HeaderName| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+        self.save(&Entry {
+            url: request.url.to_string(),
+            cookies: cookie_hash(request),
+            fetched: unix_now(),
+            etag: value(ETAG),
+            last_modified: value(LAST_MODIFIED),
+            body: body.to_string(),
+        });
+    }
+
+    /// Keep `entry` as fetched just now: the server said it has not changed.
+    pub(crate) fn renew(&self, mut entry: Entry) {
+        entry.fetched = unix_now();
+        self.save(&entry);
+    }
+
+    /// Write `entry` to its file. A cache that cannot be written to costs the
+    /// next run a fetch, which is no reason to fail this one, so errors are
+    /// dropped.
+    fn save(&self, entry: &Entry) {
+        let Some(path) = self.entry_path(&entry.url, entry.cookies) else {
+            return;
+        };
+        let _ = (|| -> crate::Result<()> {
+            fs::create_dir_all(path.parent().expect("entry paths are inside the cache directory"))?;
+            let json = serde_json::to_vec(entry)?;
+            crate::write_atomic(&path, |file| Ok(file.write_all(&json)?))
+        })();
+    }
+
+    /// Remove the entries untouched for `MAX_AGE`.
+    fn prune(&self) {
+        let Some(Ok(entries)) = self.dir.as_ref().map(fs::read_dir) else {
+            return;
+        };
+        let now = SystemTime::now();
+        for entry in entries.flatten() {
+            let old = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .is_ok_and(|at| now.duration_since(at).is_ok_and(|age| age > MAX_AGE));
+            if old {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    /// The file `url`, asked for with the cookies hashing to `cookies`, is
+    /// kept in: named by a hash of the two, as a search URL carries characters
+    /// no file name should.
+    fn entry_path(&self, url: &str, cookies: u64) -> Option<PathBuf> {
+        let hash = fnv1a(format!("{url} {cookies:016x}").as_bytes());
+        Some(self.dir.as_ref()?.join(format!("{hash:016x}.json")))
+    }
+}
+
+/// A hash of the `Cookie` header `request` carries, if any.
+fn cookie_hash(request: &Request) -> u64 {
+    fnv1a(request.headers.get(COOKIE).map_or(&[][..], |value| value.as_bytes()))
+}
+
+/// 64-bit FNV-1a: a hash that stays the same from one build to the next, which
+/// std's `DefaultHasher` does not promise.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs()
+}
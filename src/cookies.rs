@@ -0,0 +1,310 @@
+//! The cookies the site and its image hosts set, kept between runs.
+//!
+//! reqwest only keeps cookies with its `cookies` feature, and even then only
+//! for as long as the client lives. The jar here is filled from every
+//! response's `Set-Cookie` and sent back on every request by `Http`, and is
+//! written to `cookies.txt` in the config directory whenever it changes. The
+//! file is in the Netscape format browsers export, so a browser's export can
+//! be merged in with the `cookies` command to carry a logged-in session over.
+
+use crate::{config_dir, write_atomic, AppError, Result, HOST_URL};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, SET_COOKIE},
+    Url,
+};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Domains whose cookies are worth keeping: the site and its image hosts.
+/// Everything else in a browser's export is left out of the jar.
+const COOKIE_DOMAINS: &[&str] = &["manhuagui.com", "hamreus.com"];
+
+/// Sent until the site sets a `country` of its own: it picks the traditional
+/// Chinese pages, which the parsers are written against.
+const DEFAULT_COUNTRY: &str = "TW";
+
+/// The cookie the site's adult gate sets once a reader confirms they are 18 or
+/// over. Its script sets it, not a response, so the jar only has one if a
+/// browser export brought it along.
+const ADULT_COOKIE: (&str, &str) = ("isAdult", "1");
+
+const NETSCAPE_HEADER: &str = "# Netscape HTTP Cookie File";
+
+/// `cookies.txt` in the config directory, if there is one.
+pub(crate) fn jar_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("cookies.txt"))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Cookie {
+    /// Without a leading dot.
+    pub(crate) domain: String,
+    /// Whether the cookie goes to subdomains of `domain` too, rather than to
+    /// that one host only.
+    pub(crate) subdomains: bool,
+    pub(crate) path: String,
+    pub(crate) secure: bool,
+    /// Seconds since the epoch; `None` for a session cookie, which is kept
+    /// all the same — a login is often one.
+    pub(crate) expires: Option<u64>,
+    pub(crate) name: String,
+    pub(crate) value: String,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|at| at <= now)
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or_default();
+        let domain_ok = host == self.domain
+            || (self.subdomains && host.strip_suffix(&self.domain).is_some_and(|rest| rest.ends_with('.')));
+        let path = url.path();
+        let path_ok = path == self.path
+            || (path.starts_with(&self.path) && (self.path.ends_with('/') || path[self.path.len()..].starts_with('/')));
+        domain_ok && path_ok && (!self.secure || url.scheme() == "https")
+    }
+
+    /// Whether the cookie is for one of `COOKIE_DOMAINS`.
+    fn is_wanted(&self) -> bool {
+        COOKIE_DOMAINS
+            .iter()
+            .any(|d| self.domain == *d || self.domain.ends_with(&format!(".{d}")))
+    }
+
+    fn same_slot(&self, other: &Cookie) -> bool {
+        self.domain == other.domain && self.path == other.path && self.name == other.name
+    }
+
+    /// Parse one line of a Netscape cookies.txt. `None` for comments, blank
+    /// lines and lines that are not cookies.
+    fn parse_netscape(line: &str) -> Option<Cookie> {
+        // curl and browsers mark HttpOnly cookies with a prefix that would
+        // otherwise make the line a comment.
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if line.trim().is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+        let [domain, subdomains, path, secure, expires, name, value] = fields[..] else {
+            return None;
+        };
+        let expires: u64 = expires.parse().ok()?;
+        Some(Cookie {
+            subdomains: subdomains.eq_ignore_ascii_case("TRUE") || domain.starts_with('.'),
+            domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+            path: path.to_string(),
+            secure: secure.eq_ignore_ascii_case("TRUE"),
+            expires: (expires != 0).then_some(expires),
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    fn to_netscape(&self) -> String {
+        let flag = |b: bool| if b { "TRUE" } else { "FALSE" };
+        let domain = if self.subdomains { format!(".{}", self.domain) } else { self.domain.clone() };
+        format!(
+            "{domain}\t{}\t{}\t{}\t{}\t{}\t{}",
+            flag(self.subdomains),
+            self.path,
+            flag(self.secure),
+            self.expires.unwrap_or(0),
+            self.name,
+            self.value
+        )
+    }
+
+    /// Parse a `Set-Cookie` header sent in answer to a request for `url`.
+    /// `None` when it does not parse or names a domain `url` is not in.
+    fn parse_set_cookie(header: &str, url: &Url, now: u64) -> Option<Cookie> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        let mut parts = header.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        // The default path is the request path up to its last slash.
+        let default_path = match url.path().rfind('/') {
+            Some(0) | None => "/".to_string(),
+            Some(at) => url.path()[..at].to_string(),
+        };
+        let mut cookie = Cookie {
+            domain: host.clone(),
+            subdomains: false,
+            path: default_path,
+            secure: false,
+            expires: None,
+            name: name.to_string(),
+            value: value.trim().trim_matches('"').to_string(),
+        };
+        let mut max_age = None;
+        for attr in parts {
+            let (key, val) = attr.split_once('=').unwrap_or((attr, ""));
+            let val = val.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "domain" if !val.is_empty() => {
+                    cookie.domain = val.trim_start_matches('.').to_ascii_lowercase();
+                    cookie.subdomains = true;
+                }
+                "path" if val.starts_with('/') => cookie.path = val.to_string(),
+                "secure" => cookie.secure = true,
+                "max-age" => max_age = val.parse::<i64>().ok(),
+                "expires" => {
+                    // A date httpdate cannot read leaves the cookie a session
+                    // cookie, which errs on the side of keeping it.
+                    if let Ok(at) = httpdate::parse_http_date(val) {
+                        cookie.expires = Some(at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Max-Age wins over Expires; zero or less deletes the cookie.
+        if let Some(secs) = max_age {
+            cookie.expires = Some(if secs <= 0 { 0 } else { now + secs as u64 });
+        }
+        let in_domain = host == cookie.domain || host.ends_with(&format!(".{}", cookie.domain));
+        in_domain.then_some(cookie)
+    }
+}
+
+/// The jar every request draws its `cookie` header from. `path` is where it is
+/// saved; a jar without one, as in tests, lives for the run only.
+#[derive(Default)]
+pub(crate) struct CookieJar {
+    path: Option<PathBuf>,
+    cookies: Mutex<Vec<Cookie>>,
+    /// Send the adult gate's confirmation with every request to the site, as
+    /// `--accept-adult` asks. It is never saved: the confirmation holds for
+    /// the run it was given in.
+    pub(crate) accept_adult: bool,
+}
+
+impl CookieJar {
+    /// The jar saved at `path`, or an empty one if nothing is saved there yet.
+    pub(crate) fn load(path: Option<PathBuf>) -> Result<Self> {
+        let cookies = match &path {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(text) => parse_cookies_txt(&text),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(e.into()),
+            },
+            None => Vec::new(),
+        };
+        let now = unix_now();
+        let cookies = cookies.into_iter().filter(|c| !c.is_expired(now)).collect();
+        Ok(CookieJar { path, cookies: Mutex::new(cookies), accept_adult: false })
+    }
+
+    /// The `cookie` header for a request to `url`, if any cookie goes with it.
+    pub(crate) fn header_for(&self, url: &Url) -> Option<HeaderValue> {
+        let now = unix_now();
+        let cookies = self.cookies.lock().unwrap();
+        let on_site = url.host_str() == HOST_URL.host_str();
+        let confirm = on_site && self.accept_adult;
+        let mut pairs: Vec<String> = cookies
+            .iter()
+            .filter(|c| !c.is_expired(now) && c.matches(url))
+            // A saved confirmation may hold another value; the one sent is ours.
+            .filter(|c| !(confirm && c.name == ADULT_COOKIE.0))
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect();
+        if on_site && !cookies.iter().any(|c| c.name == "country" && c.matches(url)) {
+            pairs.push(format!("country={DEFAULT_COUNTRY}"));
+        }
+        if confirm {
+            pairs.push(format!("{}={}", ADULT_COOKIE.0, ADULT_COOKIE.1));
+        }
+        // A value the header cannot carry is the server's own doing; such a
+        // cookie is not sent rather than failing the request.
+        HeaderValue::from_str(&pairs.join("; ")).ok().filter(|_| !pairs.is_empty())
+    }
+
+    /// Keep the cookies a response to `url` set, saving the jar if any did.
+    pub(crate) fn store(&self, url: &Url, headers: &HeaderMap) -> Result<()> {
+        let now = unix_now();
+        let set: Vec<Cookie> = headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| Cookie::parse_set_cookie(value, url, now))
+            .filter(Cookie::is_wanted)
+            .collect();
+        if set.is_empty() {
+            return Ok(());
+        }
+        self.merge(set, now);
+        self.save()
+    }
+
+    /// Merge a browser's cookies.txt export into the jar, keeping only the
+    /// cookies for `COOKIE_DOMAINS`. Returns how many were taken.
+    pub(crate) fn import(&self, text: &str) -> Result<usize> {
+        let now = unix_now();
+        let wanted: Vec<Cookie> = parse_cookies_txt(text)
+            .into_iter()
+            .filter(|c| !c.is_expired(now) && c.is_wanted())
+            .collect();
+        let count = wanted.len();
+        self.merge(wanted, now);
+        self.save()?;
+        Ok(count)
+    }
+
+    fn merge(&self, cookies: Vec<Cookie>, now: u64) {
+        let mut jar = self.cookies.lock().unwrap();
+        for cookie in cookies {
+            jar.retain(|c| !c.same_slot(&cookie));
+            if !cookie.is_expired(now) {
+                jar.push(cookie);
+            }
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let text = {
+            let cookies = self.cookies.lock().unwrap();
+            let mut text = format!("{NETSCAPE_HEADER}\n# Written by mhg_dl_rs; edit with care.\n\n");
+            for cookie in cookies.iter() {
+                text.push_str(&cookie.to_netscape());
+                text.push('\n');
+            }
+            text
+        };
+        write_atomic(path, |file| Ok(file.write_all(text.as_bytes())?))
+    }
+}
+
+/// Every cookie in a Netscape cookies.txt, skipping lines that are not one.
+pub(crate) fn parse_cookies_txt(text: &str) -> Vec<Cookie> {
+    text.lines().filter_map(Cookie::parse_netscape).collect()
+}
+
+/// Read a browser's cookies.txt export, refusing a file that holds no cookies
+/// at all: that is the wrong file, or one in another format.
+pub(crate) fn read_export(path: &Path) -> Result<String> {
+    let text = fs::read_to_string(path)?;
+    if parse_cookies_txt(&text).is_empty() {
+        return Err(AppError::ContentParsing(format!(
+            "{} has no cookies in the Netscape cookies.txt format",
+            path.display()
+        )));
+    }
+    Ok(text)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs()
+}
@@ -0,0 +1,267 @@
+//! Everything the program reports besides its output proper, at the level
+//! `-q`, `-v` and `-vv` pick, and the `--log-file` record of every request.
+//!
+//! Notices go through the `log` macros. On the terminal they are bare lines on
+//! stderr, printed through `BARS` so that a progress bar being drawn is cleared
+//! first and redrawn after; while the full-screen interface is up they are
+//! held back until it is gone. The log file gets every line down to debug
+//! level whatever the terminal shows, with the time and level in front, and
+//! `Logged` puts a line there for every request made.
+
+use crate::{
+    transport::{Body, BoxFuture, Reply, Request, Transport},
+    Result,
+};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget};
+use log::{LevelFilter, Log, Metadata, Record};
+use reqwest::{StatusCode, Url};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::{LazyLock, Mutex, OnceLock},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// How much is reported, and where.
+#[derive(Clone, Debug, Default, clap::Args)]
+pub(crate) struct LogOptions {
+    /// Report every request (-v), and the headers sent (-vv)
+    #[clap(short, long, global = true, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    pub(crate) verbose: u8,
+    /// Report errors only, without progress bars
+    #[clap(short, long, global = true)]
+    pub(crate) quiet: bool,
+    /// Append every request, with its status, size and timing, to FILE
+    #[clap(long, value_name = "FILE", global = true)]
+    pub(crate) log_file: Option<PathBuf>,
+}
+
+impl LogOptions {
+    /// What the terminal shows.
+    pub(crate) fn terminal_level(&self) -> LevelFilter {
+        match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::Error,
+            (false, 0) => LevelFilter::Info,
+            (false, 1) => LevelFilter::Debug,
+            (false, _) => LevelFilter::Trace,
+        }
+    }
+
+    /// What the log file records: never less than every request.
+    pub(crate) fn file_level(&self) -> LevelFilter {
+        self.terminal_level().max(LevelFilter::Debug)
+    }
+}
+
+/// The progress bars on the terminal. Bars are added with `track`, so that a
+/// line logged while one is drawn does not end up in the middle of it.
+static BARS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+/// Terminal lines held back while `Held` lives.
+static HELD: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+struct Logger {
+    terminal: LevelFilter,
+    file: Option<(LevelFilter, Mutex<File>)>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // reqwest logs through the same facade; only our own lines are wanted.
+        metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+            && (metadata.level() <= self.terminal
+                || self.file.as_ref().is_some_and(|(level, _)| metadata.level() <= *level))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if record.level() <= self.terminal {
+            let line = record.args().to_string();
+            let line = match HELD.lock().unwrap().as_mut() {
+                Some(held) => {
+                    held.push(line);
+                    None
+                }
+                None => Some(line),
+            };
+            if let Some(line) = line {
+                BARS.suspend(|| eprintln!("{line}"));
+            }
+        }
+        if let Some((level, file)) = &self.file
+            && record.level() <= *level
+        {
+            let line = format!("{} {:<5} {}\n", timestamp(SystemTime::now()), record.level(), record.args());
+            // A log file that cannot be written to is no reason to stop a
+            // download.
+            let _ = file.lock().unwrap().write_all(line.as_bytes());
+        }
+    }
+
+    fn flush(&self) {
+        if let Some((_, file)) = &self.file {
+            let _ = file.lock().unwrap().flush();
+        }
+    }
+}
+
+/// Start logging as `opts` asks. The terminal side is set up even when the log
+/// file cannot be opened, so that the error saying so is seen.
+pub(crate) fn init(opts: &LogOptions) -> Result<()> {
+    if opts.quiet {
+        BARS.set_draw_target(ProgressDrawTarget::hidden());
+    }
+    let file = opts.log_file.as_ref().map(|path| {
+        OpenOptions::new().create(true).append(true).open(path).map_err(|e| {
+            io::Error::new(e.kind(), format!("cannot open log file {}: {e}", path.display()))
+        })
+    });
+    let (file, error) = match file {
+        Some(Ok(file)) => (Some((opts.file_level(), Mutex::new(file))), None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+    let logger = LOGGER.get_or_init(|| Logger { terminal: opts.terminal_level(), file });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(logger.file.as_ref().map_or(logger.terminal, |(level, _)| logger.terminal.max(*level)));
+    }
+    match error {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+/// `bar`, drawn among the terminal's progress bars.
+pub(crate) fn track(bar: ProgressBar) -> ProgressBar {
+    BARS.add(bar)
+}
+
+/// Hold back terminal lines for as long as the result lives, then print
+/// them: the full-screen interface owns the terminal until it is dropped.
+pub(crate) fn hold() -> Held {
+    *HELD.lock().unwrap() = Some(Vec::new());
+    Held(())
+}
+
+pub(crate) struct Held(());
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        for line in HELD.lock().unwrap().take().unwrap_or_default() {
+            eprintln!("{line}");
+        }
+    }
+}
+
+/// `time` in UTC as RFC 3339, to the millisecond.
+pub(crate) fn timestamp(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let (days, rest) = ((secs / 86_400) as i64, secs % 86_400);
+    // Days since the epoch to a civil date, after Howard Hinnant's
+    // `civil_from_days`.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60,
+        since.subsec_millis()
+    )
+}
+
+/// A `Transport` that logs every request it carries at debug level, once the
+/// answer's body has been read or let go of, so that the line can say how
+/// much of it there was. The headers sent are logged at trace level, all but
+/// the cookies.
+pub(crate) struct Logged<T>(pub(crate) T);
+
+impl<T: Transport> Transport for Logged<T> {
+    fn send<'a>(&'a self, request: &'a Request) -> BoxFuture<'a, Result<Reply>> {
+        Box::pin(async move {
+            for (name, value) in request.headers.iter().filter(|(name, _)| *name != "cookie") {
+                log::trace!("  {name}: {}", value.to_str().unwrap_or("(not text)"));
+            }
+            let started = Instant::now();
+            match self.0.send(request).await {
+                Ok(mut reply) => {
+                    let redirected = (reply.url != request.url).then(|| reply.url.clone());
+                    reply.body = Box::new(Counted {
+                        body: reply.body,
+                        url: request.url.clone(),
+                        redirected,
+                        status: reply.status,
+                        started,
+                        bytes: 0,
+                        logged: false,
+                    });
+                    Ok(reply)
+                }
+                Err(e) => {
+                    log::debug!("GET {} failed after {} ms: {e}", request.url, started.elapsed().as_millis());
+                    Err(e)
+                }
+            }
+        })
+    }
+}
+
+/// A body that counts what is read of it, for `Logged`.
+struct Counted {
+    body: Box<dyn Body>,
+    url: Url,
+    redirected: Option<Url>,
+    status: StatusCode,
+    started: Instant,
+    bytes: u64,
+    logged: bool,
+}
+
+impl Counted {
+    fn log(&mut self, end: &str) {
+        self.logged = true;
+        let via = self.redirected.as_ref().map(|to| format!(" -> {to}")).unwrap_or_default();
+        log::debug!(
+            "GET {}{via}: {}, {} bytes in {} ms{end}",
+            self.url,
+            self.status,
+            self.bytes,
+            self.started.elapsed().as_millis()
+        );
+    }
+}
+
+impl Body for Counted {
+    fn chunk(&mut self) -> BoxFuture<'_, Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            let chunk = self.body.chunk().await;
+            match &chunk {
+                Ok(Some(piece)) => self.bytes += piece.len() as u64,
+                Ok(None) => self.log(""),
+                Err(e) => self.log(&format!(", then broke off: {e}")),
+            }
+            chunk
+        })
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        if !self.logged {
+            self.log(" (not read to the end)");
+        }
+    }
+}
@@ -1,35 +1,43 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use crossterm::{
     event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
     terminal,
 };
 use indicatif::{ProgressBar, ProgressStyle};
+use logging::LogOptions;
+use pages::PageOptions;
+use progress::Progress;
+use summary::{FailOn, Summary, Tally};
+use cache::{CacheOptions, Cached, HtmlCache, Lifetime};
+use cookies::CookieJar;
 use reqwest::{
-    blocking::Client,
     header::{HeaderMap, HeaderValue},
-    Url,
+    Client,
+    StatusCode, Url,
 };
 use scraper::{Html, Selector};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use regex::Regex;
-use rand::Rng;
 use std::{
     fs,
     io::{self, Read, Write},
-    num::ParseIntError,
     path::{Path, PathBuf},
     process::ExitCode,
     sync::LazyLock,
-    thread,
     time::Duration,
 };
 use thiserror::Error;
+use throttle::{PaceOptions, Throttle};
+use tokio::runtime::Runtime;
+use transport::{Reply, Request, Transport};
 use zip::{result::ZipError, write::FileOptions, CompressionMethod, ZipWriter};
 
 const HOST: &str = "https://tw.manhuagui.com";
 const TUNNEL_CHANNELS: [&str; 3] = ["i", "eu", "us"];
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Listing pages fetched without asking, unless `--max-pages` says otherwise.
+const DEFAULT_MAX_PAGES: usize = 5;
 
 /// `HOST` parsed once, as the base every site-relative link is resolved against.
 /// Parsing also normalizes it to a trailing slash, which is what the site root
@@ -64,27 +72,58 @@ static RE_ILLEGAL_CHARS: LazyLock<Regex> =
 static SEL_COMICS: LazyLock<Selector> =
     LazyLock::new(|| Selector::parse("div.book-result ul li.cf").unwrap());
 static SEL_LINK: LazyLock<Selector> = LazyLock::new(|| Selector::parse("a.bcover").unwrap());
+static SEL_IMG: LazyLock<Selector> = LazyLock::new(|| Selector::parse("img").unwrap());
+/// The labelled lines of a search hit, the counterpart of `SEL_DETAIL_FIELDS`.
+static SEL_RESULT_FIELDS: LazyLock<Selector> =
+    LazyLock::new(|| Selector::parse("dd > span").unwrap());
+static SEL_RESULT_STATUS: LazyLock<Selector> =
+    LazyLock::new(|| Selector::parse("dd.status").unwrap());
 static SEL_TITLE: LazyLock<Selector> =
     LazyLock::new(|| Selector::parse(".book-title h1").unwrap());
+static SEL_SUBTITLE: LazyLock<Selector> =
+    LazyLock::new(|| Selector::parse(".book-title h2").unwrap());
 /// A chapter link inside a `.chapter-list`. Both attributes are required by the
 /// selector so that non-chapter anchors — the `<a id="v1" href="javascript:;">`
 /// pager entries that sit in a sibling block today, ad links, "more" links — are
 /// simply not matched, rather than aborting the whole book's parse.
 static SEL_CHAPTER_LINK: LazyLock<Selector> =
     LazyLock::new(|| Selector::parse("a[href][title]").unwrap());
+/// One entry of a browse listing: the category grid, the update page and the
+/// ranking table each lay their entries out differently, but every entry links
+/// to the comic's `/comic/<id>/` page.
+static SEL_LISTING_ITEMS: LazyLock<Selector> = LazyLock::new(|| {
+    Selector::parse("#contList > li, div.latest-list li, div.top-cont tr").unwrap()
+});
+static SEL_HREF: LazyLock<Selector> = LazyLock::new(|| Selector::parse("a[href]").unwrap());
 static SEL_PAGER_LINKS: LazyLock<Selector> =
     LazyLock::new(|| Selector::parse("div.pager a").unwrap());
 static SEL_VIEWSTATE: LazyLock<Selector> =
     LazyLock::new(|| Selector::parse("input#__VIEWSTATE").unwrap());
+/// The "I am 18 or over" link in the warning block the site shows in place of
+/// an adult comic's chapter list, and of a gated chapter's pages.
+static SEL_ADULT_GATE: LazyLock<Selector> =
+    LazyLock::new(|| Selector::parse("#checkAdult").unwrap());
 static SEL_CHAPTER_LIST: LazyLock<Selector> =
     LazyLock::new(|| Selector::parse(".chapter-list").unwrap());
+/// The labelled fields of the detail block: each `span` holds a `strong` label
+/// followed by its value. Direct children only, so the `span.red` values nested
+/// inside the status line are not taken for fields of their own.
+static SEL_DETAIL_FIELDS: LazyLock<Selector> =
+    LazyLock::new(|| Selector::parse("ul.detail-list li > span").unwrap());
+static SEL_DETAIL_STATUS: LazyLock<Selector> =
+    LazyLock::new(|| Selector::parse("ul.detail-list li.status").unwrap());
+static SEL_STRONG: LazyLock<Selector> = LazyLock::new(|| Selector::parse("strong").unwrap());
+static SEL_ANCHOR: LazyLock<Selector> = LazyLock::new(|| Selector::parse("a").unwrap());
+static SEL_RED: LazyLock<Selector> = LazyLock::new(|| Selector::parse("span.red").unwrap());
+static SEL_INTRO: LazyLock<Selector> =
+    LazyLock::new(|| Selector::parse("#intro-all, #intro-cut").unwrap());
 
 /// Per-chapter download bar. The template is fixed, so parse it once instead of
 /// re-parsing (and re-`unwrap`ping) it for every chapter.
 static BAR_STYLE: LazyLock<ProgressStyle> = LazyLock::new(|| {
     ProgressStyle::default_bar()
         .template(
-            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}",
+            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {prefix} {msg}",
         )
         .unwrap()
         .progress_chars("#>-")
@@ -98,40 +137,503 @@ enum AppError {
     Interrupted,
     #[error("No comics found for '{0}'")]
     NoSearchResults(String),
+    #[error("Invalid selection: {0}")]
+    InvalidSelection(String),
+    #[error("{0} archive(s) failed verification")]
+    VerifyFailed(usize),
+    #[error("{0} of {1} chapter(s) failed")]
+    ChaptersFailed(usize, usize),
     #[error("Content parsing error: {0}")]
     ContentParsing(String),
     #[error("I/O error: {0}")]
     Io(#[from] io::Error),
     #[error("Network request error: {0}")]
     Reqwest(#[from] reqwest::Error),
+    #[error("HTTP status {0} for {1}")]
+    Status(StatusCode, Url),
     #[error("JSON parsing error: {0}")]
     SerdeJson(#[from] serde_json::Error),
-    #[error("Integer parsing error: {0}")]
-    ParseInt(#[from] ParseIntError),
     #[error("Zip error: {0}")]
     Zip(#[from] ZipError),
+    #[error("Image error: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("Damaged image: {0}")]
+    BadImage(String),
+    #[error("{0} is behind the adult gate; pass --accept-adult to confirm you are 18 or over")]
+    AdultGated(String),
+}
+
+impl AppError {
+    /// The exit status a run that ends in this error has, by category.
+    /// Scripts act on these, so they are listed in the README and a code is
+    /// never given another meaning; a new kind of error either joins a
+    /// category or gets a new number. clap exits with 2 on a command line it
+    /// cannot parse, which no `AppError` uses for that reason.
+    fn exit_code(&self) -> u8 {
+        match self {
+            AppError::InvalidUrl => 3,
+            AppError::InvalidSelection(_) => 4,
+            AppError::NoSearchResults(_) => 5,
+            AppError::Reqwest(_) | AppError::Status(..) => 6,
+            AppError::Io(e) if is_network_error(e) => 6,
+            AppError::ContentParsing(_) => 7,
+            AppError::AdultGated(_) => 8,
+            AppError::Io(_) | AppError::Zip(_) => 9,
+            // Chapter data that does not read is a `ContentParsing`; what is
+            // left is writing JSON out.
+            AppError::SerdeJson(e) if e.is_io() => 9,
+            AppError::BadImage(_) | AppError::Image(_) => 10,
+            AppError::ChaptersFailed(..) => 11,
+            AppError::VerifyFailed(_) => 12,
+            // 128 + SIGINT, as a shell reports a program Ctrl+C stopped.
+            AppError::Interrupted => 130,
+            AppError::SerdeJson(_) => 1,
+        }
+    }
+}
+
+/// Whether `e` came from the network rather than the disk: a transport that
+/// timed out, or a response body that broke off, which `transport` hands on
+/// as an `io::Error` so that it is retried like a short read.
+fn is_network_error(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::TimedOut || e.get_ref().is_some_and(|inner| inner.is::<reqwest::Error>())
 }
 
 type Result<T> = std::result::Result<T, AppError>;
 
 /// Simple Manhuagui downloader in Rust
 #[derive(Parser)]
-#[clap(author, version, about)]
+#[clap(author, version, about, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Manhuagui URL or numeric ID
-    #[clap(value_name = "URL", required_unless_present = "search", conflicts_with = "search")]
+    #[clap(subcommand)]
+    command: Option<Command>,
+    /// Manhuagui URL or numeric ID to download; shorthand for `download <URL>`
+    #[clap(value_name = "URL", required_unless_present = "search")]
     url: Option<String>,
-    /// Search keyword for comics
-    #[clap(short, long)]
+    /// Shorthand for `search <KEYWORD>`, from before there were commands
+    #[clap(short, long, value_name = "KEYWORD", hide = true, conflicts_with = "url")]
     search: Option<String>,
+    #[clap(flatten)]
+    download: DownloadOpts,
+    #[clap(flatten)]
+    log: LogOptions,
+}
+
+/// Where and how chapters are downloaded; shared by every command that
+/// downloads.
+#[derive(clap::Args)]
+struct DownloadOpts {
     #[clap(short, long, default_value_t = 0, help = TUNNEL_HELP.as_str(), value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(..TUNNEL_CHANNELS.len() as u64))]
     tunnel: usize,
-    /// Delay between pages in milliseconds
-    #[clap(short, long, default_value_t = 1000)]
-    delay_ms: u64,
     /// Output directory
     #[clap(short, long, default_value = "Downloads")]
     output_dir: PathBuf,
+    /// Use line prompts instead of the full-screen interface
+    #[clap(long)]
+    no_tui: bool,
+    /// How to show progress: bars, or JSON events on stdout
+    #[clap(long, value_enum, default_value = "bars")]
+    progress: Progress,
+    /// Exit with an error if any chapter failed, or only if all did
+    #[clap(long, value_enum, default_value = "any")]
+    fail_on: FailOn,
+    /// Confirm you are 18 or over, to open adult-gated comics
+    #[clap(long)]
+    accept_adult: bool,
+    #[clap(flatten)]
+    pace: PaceOptions,
+    #[clap(flatten)]
+    cache: CacheOptions,
+    #[clap(flatten)]
+    pages: PageOptions,
+}
+
+impl DownloadOpts {
+    /// Whether to pick and download on the full screen rather than with line
+    /// prompts and progress bars. Never with `--progress json`: the events
+    /// are for a program, which cannot read the screen.
+    fn use_tui(&self) -> bool {
+        !self.no_tui && !self.progress.is_json() && tui::available()
+    }
+
+    /// The `Http` a job under these options makes its requests through.
+    fn http(&self) -> Result<Http> {
+        Http::new(Throttle::new(&self.pace), &self.cache, self.accept_adult)
+    }
+}
+
+/// How a comic is chosen from a search or browse listing.
+#[derive(clap::Args)]
+struct PickOpts {
+    /// Print every result as JSON instead of choosing one
+    #[clap(long, conflicts_with_all = &["pick", "first", "chapters", "matching", "exclude", "group"])]
+    json: bool,
+    /// Download the Nth result without asking
+    #[clap(long, value_name = "N", conflicts_with = "first", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pick: Option<usize>,
+    /// Download the first result without asking; same as `--pick 1`
+    #[clap(long)]
+    first: bool,
+    /// Result pages to fetch without asking, with --json, --pick or --first
+    #[clap(long, value_name = "N", default_value_t = DEFAULT_MAX_PAGES, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    max_pages: usize,
+    #[clap(flatten)]
+    select: ChapterOpts,
+    /// Only keep results of ongoing (lianzai) or completed (wanjie) series
+    #[clap(long, value_name = "STATUS", value_enum)]
+    only: Option<SeriesStatus>,
+    /// Only keep results by an author whose name contains this text
+    #[clap(long, value_name = "TEXT")]
+    author: Option<String>,
+    /// Order of the results: each page as it is shown, or all of them with
+    /// --json, --pick or --first
+    #[clap(long, value_enum, default_value = "site")]
+    sort_by: ResultOrder,
+}
+
+impl Default for PickOpts {
+    fn default() -> Self {
+        PickOpts {
+            json: false,
+            pick: None,
+            first: false,
+            max_pages: DEFAULT_MAX_PAGES,
+            select: ChapterOpts::default(),
+            only: None,
+            author: None,
+            sort_by: ResultOrder::Site,
+        }
+    }
+}
+
+impl PickOpts {
+    fn filter(&self) -> ResultFilter {
+        ResultFilter {
+            status: self.only,
+            author: self.author.clone(),
+            order: self.sort_by,
+        }
+    }
+}
+
+/// Which chapters to download without asking: by number, by name, or both,
+/// in which case the numbers are narrowed by the names.
+#[derive(Default, clap::Args)]
+struct ChapterOpts {
+    /// Chapters to download (e.g. 1-3,5) instead of asking
+    #[clap(short, long, value_name = "SELECTION")]
+    chapters: Option<String>,
+    #[clap(flatten)]
+    names: NameFilter,
+}
+
+/// Chapters picked by name rather than by number. Numbers shift whenever the
+/// site slots chapters into an earlier block of the list; names do not.
+#[derive(Default, clap::Args)]
+struct NameFilter {
+    /// Only chapters whose name matches REGEX
+    #[clap(long = "match", value_name = "REGEX", value_parser = Regex::new)]
+    matching: Option<Regex>,
+    /// Leave out chapters whose name matches REGEX
+    #[clap(long, value_name = "REGEX", value_parser = Regex::new)]
+    exclude: Option<Regex>,
+    /// Only chapters under a group heading (e.g. 單話) that matches REGEX
+    #[clap(long, value_name = "REGEX", value_parser = Regex::new)]
+    group: Option<Regex>,
+}
+
+impl NameFilter {
+    fn is_empty(&self) -> bool {
+        self.matching.is_none() && self.exclude.is_none() && self.group.is_none()
+    }
+
+    fn keeps(&self, chapter: &Chapter) -> bool {
+        self.matching.as_ref().is_none_or(|re| re.is_match(&chapter.name))
+            && !self.exclude.as_ref().is_some_and(|re| re.is_match(&chapter.name))
+            && self.group.as_ref().is_none_or(|re| re.is_match(&chapter.group))
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ResultOrder {
+    /// As the site lists them
+    Site,
+    /// Most recently updated first
+    Updated,
+    /// By title
+    Title,
+}
+
+/// Narrowing and ordering of listing results, done locally on what the site
+/// returned rather than by asking it for something else.
+struct ResultFilter {
+    status: Option<SeriesStatus>,
+    author: Option<String>,
+    order: ResultOrder,
+}
+
+impl ResultFilter {
+    /// A result the listing says nothing about — no status, no authors — is
+    /// not kept by a filter on it: there is no telling whether it matches.
+    fn keeps(&self, r: &SearchResult) -> bool {
+        let status_ok = self.status.is_none_or(|wanted| {
+            let word = match wanted {
+                SeriesStatus::Lianzai => "連載",
+                SeriesStatus::Wanjie => "完結",
+            };
+            r.status.as_deref().is_some_and(|s| s.contains(word))
+        });
+        let author_ok = self.author.as_deref().is_none_or(|wanted| {
+            let wanted = wanted.to_lowercase();
+            r.authors.iter().any(|a| a.to_lowercase().contains(&wanted))
+        });
+        status_ok && author_ok
+    }
+
+    /// Sort in place; stable, so ties keep the site's order.
+    fn sort(&self, results: &mut [SearchResult]) {
+        match self.order {
+            ResultOrder::Site => {}
+            // Dates are ISO `YYYY-MM-DD`, so their text sorts chronologically;
+            // a result without one goes last.
+            ResultOrder::Updated => {
+                results.sort_by(|a, b| b.last_update.cmp(&a.last_update))
+            }
+            ResultOrder::Title => results.sort_by(|a, b| a.title.cmp(&b.title)),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum BrowseListing {
+    /// Comics filtered by category, newest first unless sorted otherwise
+    Category {
+        /// Only comics from this region
+        #[clap(long, value_enum)]
+        region: Option<Region>,
+        /// Genre as the site spells it in its URLs, e.g. rexue, maoxian, aiqing
+        #[clap(long)]
+        genre: Option<String>,
+        /// Only comics for this audience: girls, boys, young adults, children or all ages
+        #[clap(long, value_enum)]
+        audience: Option<Audience>,
+        /// Only ongoing (lianzai) or completed (wanjie) series
+        #[clap(long, value_enum)]
+        status: Option<SeriesStatus>,
+        /// Order of the listing
+        #[clap(long, value_enum, default_value = "index")]
+        sort: ListSort,
+        #[clap(flatten)]
+        pick: PickOpts,
+        #[clap(flatten)]
+        download: DownloadOpts,
+    },
+    /// The most viewed comics of a period
+    Rank {
+        /// Ranking period
+        #[clap(value_enum, default_value = "day")]
+        period: RankPeriod,
+        #[clap(flatten)]
+        pick: PickOpts,
+        #[clap(flatten)]
+        download: DownloadOpts,
+    },
+    /// The comics updated most recently
+    Updates {
+        #[clap(flatten)]
+        pick: PickOpts,
+        #[clap(flatten)]
+        download: DownloadOpts,
+    },
+}
+
+/// The category filters and orders below are the slugs the site builds its
+/// `/list/…` URLs from, spelled as it spells them.
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Region {
+    Japan,
+    Hongkong,
+    China,
+    Korea,
+    Europe,
+    Other,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Audience {
+    Shaonv,
+    Shaonian,
+    Qingnian,
+    Ertong,
+    Tongyong,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum SeriesStatus {
+    /// Ongoing
+    #[clap(alias = "ongoing")]
+    Lianzai,
+    /// Completed
+    #[clap(alias = "completed")]
+    Wanjie,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ListSort {
+    /// Newest first
+    Index,
+    /// Most recently updated first
+    Update,
+    /// Most viewed first
+    View,
+    /// Best rated first
+    Rate,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum RankPeriod {
+    Day,
+    Week,
+    Month,
+    Total,
+}
+
+/// The slug of a `clap::ValueEnum` value, which is its site spelling.
+fn slug(value: impl clap::ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .expect("no listing value is skipped")
+        .get_name()
+        .to_string()
+}
+
+/// The site path of a category listing: the filters that are set, joined by
+/// `_` in the site's order, then the sort. An unsorted, unfiltered listing is
+/// `/list/` itself.
+fn category_path(
+    region: Option<Region>,
+    genre: Option<&str>,
+    audience: Option<Audience>,
+    status: Option<SeriesStatus>,
+    sort: ListSort,
+) -> String {
+    let filters: Vec<String> = [
+        region.map(slug),
+        genre.map(|g| g.trim().to_lowercase()).filter(|g| !g.is_empty()),
+        audience.map(slug),
+        status.map(slug),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let mut path = String::from("/list/");
+    if !filters.is_empty() {
+        path.push_str(&urlencoding::encode(&filters.join("_")));
+        path.push('/');
+    }
+    if !matches!(sort, ListSort::Index) {
+        path.push_str(&format!("{}.html", slug(sort)));
+    }
+    path
+}
+
+/// The site path of a ranking page; the daily ranking is the index.
+fn rank_path(period: RankPeriod) -> String {
+    match period {
+        RankPeriod::Day => "/rank/".to_string(),
+        other => format!("/rank/{}.html", slug(other)),
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Search for a comic by keyword, then pick chapters to download
+    Search {
+        /// Search keyword
+        keyword: String,
+        #[clap(flatten)]
+        pick: PickOpts,
+        #[clap(flatten)]
+        download: DownloadOpts,
+    },
+    /// Browse the category, ranking or update listings, then pick chapters to download
+    Browse {
+        #[clap(subcommand)]
+        listing: BrowseListing,
+    },
+    /// Print a comic's details and chapter list as JSON, without prompting
+    Info {
+        /// Manhuagui URL or numeric ID
+        #[clap(value_name = "URL")]
+        url: String,
+        /// Output directory the download status is read from
+        #[clap(short, long, default_value = "Downloads")]
+        output_dir: PathBuf,
+        /// Confirm you are 18 or over, to open adult-gated comics
+        #[clap(long)]
+        accept_adult: bool,
+        #[clap(flatten)]
+        cache: CacheOptions,
+    },
+    /// Download chapters of a comic
+    Download {
+        /// Manhuagui URL or numeric ID
+        #[clap(value_name = "URL")]
+        url: String,
+        #[clap(flatten)]
+        select: ChapterOpts,
+        #[clap(flatten)]
+        download: DownloadOpts,
+    },
+    /// Download every chapter of a comic that is not in the output directory yet
+    Update {
+        /// Manhuagui URL or numeric ID
+        #[clap(value_name = "URL")]
+        url: String,
+        #[clap(flatten)]
+        names: NameFilter,
+        #[clap(flatten)]
+        download: DownloadOpts,
+    },
+    /// Check that downloaded archives are complete and contain only images
+    Verify {
+        /// Comic directories to check, as shown by `list` [default: all]
+        #[clap(value_name = "COMIC")]
+        comics: Vec<String>,
+        /// Output directory
+        #[clap(short, long, default_value = "Downloads")]
+        output_dir: PathBuf,
+        /// Delete archives that fail, so that `update` downloads them again
+        #[clap(long)]
+        delete: bool,
+    },
+    /// List the comics in the output directory and how many chapters each has
+    List {
+        /// Output directory
+        #[clap(short, long, default_value = "Downloads")]
+        output_dir: PathBuf,
+    },
+    /// Add pages, such as a scanlator's credit page, to the --drop-blocked list
+    Block {
+        /// Page images, or .cbz archives to take the page --page names from
+        #[clap(value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+        /// Page of each archive to block, counting from 1; -1 is the last
+        #[clap(long, value_name = "N", allow_hyphen_values = true)]
+        page: Option<isize>,
+        /// What the page is, kept next to its hash [default: where it came from]
+        #[clap(long)]
+        note: Option<String>,
+        /// Blocklist file [default: blocklist.txt in the config directory]
+        #[clap(long, value_name = "FILE")]
+        blocklist: Option<PathBuf>,
+    },
+    /// Import the site's cookies from a browser's cookies.txt export
+    Cookies {
+        /// A cookies.txt in the Netscape format, as browser extensions export it
+        #[clap(value_name = "FILE")]
+        file: PathBuf,
+    },
 }
 
 /// Extract a comic ID from a bare number, an absolute manhuagui comic URL,
@@ -176,13 +678,46 @@ impl std::fmt::Display for NumOrStr {
     }
 }
 
-#[derive(Debug)]
+/// One comic in a search or browse listing. Everything past the title and ID
+/// is what the listing shows about the comic, and is left empty when it shows
+/// nothing — the browse listings carry much less than a search does.
+#[derive(Debug, Clone, Default, Serialize)]
 struct SearchResult {
     title: String,
     comic_id: usize,
+    authors: Vec<String>,
+    /// "連載" or "完結", as the site words it.
+    status: Option<String>,
+    latest_chapter: Option<String>,
+    last_update: Option<String>,
+    cover_url: Option<String>,
+    aliases: Vec<String>,
 }
 
-#[derive(Debug)]
+impl SearchResult {
+    /// The title followed by whatever else is known, for the numbered list.
+    fn describe(&self) -> String {
+        let mut line = self.title.clone();
+        if let Some(status) = &self.status {
+            line.push_str(&format!(" [{status}]"));
+        }
+        if !self.authors.is_empty() {
+            line.push_str(&format!(" {}", self.authors.join("/")));
+        }
+        if let Some(latest) = &self.latest_chapter {
+            line.push_str(&format!(" — {latest}"));
+            if let Some(date) = &self.last_update {
+                line.push_str(&format!(" ({date})"));
+            }
+        }
+        if !self.aliases.is_empty() {
+            line.push_str(&format!("\n     a.k.a. {}", self.aliases.join(", ")));
+        }
+        line
+    }
+}
+
+#[derive(Debug, Serialize)]
 struct Chapter {
     name: String,
     href: String,
@@ -190,14 +725,55 @@ struct Chapter {
     group: String,
 }
 
+/// Where a comic's chapter list was read from.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ChapterSource {
+    /// The list the page shows.
+    List,
+    /// The copy an adult-gated page keeps in its `__VIEWSTATE` field while
+    /// the visible list is replaced by the gate's warning.
+    ViewState,
+}
+
+/// What the comic's detail block says about it. Every field is optional: the
+/// block is free-form enough that a comic missing a line is normal, and none of
+/// it is needed to download anything.
+#[derive(Debug, Default, Serialize)]
+struct ComicMetadata {
+    /// Alternate title shown under the main one, e.g. the original Japanese name.
+    subtitle: Option<String>,
+    year: Option<String>,
+    region: Option<String>,
+    genres: Vec<String>,
+    authors: Vec<String>,
+    aliases: Vec<String>,
+    /// "連載中" or "已完結", as the site words it.
+    status: Option<String>,
+    last_update: Option<String>,
+    latest_chapter: Option<String>,
+    description: Option<String>,
+}
+
 struct Comic {
-    client: Client,
+    http: Http,
+    id: usize,
     /// Image host, parsed once so a page's `path` is resolved against it rather
     /// than concatenated onto it. See `Comic::image_url`.
     tunnel: Url,
-    delay: Duration,
+    /// What is done to each chapter's pages before they are packed.
+    pages: PageOptions,
     title: String,
+    metadata: ComicMetadata,
     chapters: Vec<Chapter>,
+    chapter_source: ChapterSource,
+    /// How downloads show their progress; bars unless `--progress` says
+    /// otherwise.
+    progress: Progress,
+    /// Whether failed chapters fail the run; see `Summary::verdict`.
+    fail_on: FailOn,
+    /// What the downloads have fetched so far, for the summary.
+    tally: Tally,
     /// Sanitized title, used as the book directory name and zip name prefix.
     book_safe: String,
     book_dir: PathBuf,
@@ -272,11 +848,23 @@ fn write_atomic(dst: &Path, write: impl FnOnce(&mut fs::File) -> Result<()>) ->
 }
 
 /// How many bytes of a response `looks_like_image` needs to see. The longest
-/// signature it checks is WebP's, whose `WEBP` tag sits at offset 8.
+/// signatures it checks are WebP's and AVIF's, whose `WEBP` tag and brand sit
+/// at offset 8.
 const IMAGE_HEAD_LEN: usize = 12;
 
-/// Whether `head` — the first `IMAGE_HEAD_LEN` bytes of a response — opens with
-/// the signature of one of the two formats the tunnel serves.
+/// How many times a page that arrives damaged is fetched before the chapter
+/// fails.
+const PAGE_ATTEMPTS: usize = 3;
+
+/// A page as `Comic::fetch_page` received it, checked in full.
+struct FetchedPage {
+    data: Vec<u8>,
+    format: PageFormat,
+    /// The response's Content-Type, if it names a format other than `format`.
+    mislabelled: Option<String>,
+}
+
+/// The formats a page may come in.
 ///
 /// This is an allow list rather than a "does not look like HTML" test: what has
 /// to be kept out is anything that is *not* an image, and an anti-hotlink or
@@ -285,9 +873,87 @@ const IMAGE_HEAD_LEN: usize = 12;
 /// site adds later is rejected until it is added here — which surfaces as the
 /// caller's error, naming the bytes it saw, rather than as a corrupt page
 /// sealed inside a finished .cbz.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PageFormat {
+    Jpeg,
+    Png,
+    Gif,
+    WebP,
+    Avif,
+}
+
+impl PageFormat {
+    const ALL: [PageFormat; 5] = [Self::Jpeg, Self::Png, Self::Gif, Self::WebP, Self::Avif];
+
+    /// The format whose signature `head` — the first `IMAGE_HEAD_LEN` bytes of
+    /// a response — opens with.
+    fn sniff(head: &[u8]) -> Option<Self> {
+        if head.starts_with(b"\xFF\xD8\xFF") {
+            Some(Self::Jpeg)
+        } else if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Self::Png)
+        } else if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+            Some(Self::Gif)
+        } else if is_webp(head) {
+            Some(Self::WebP)
+        } else if head.get(4..8) == Some(&b"ftyp"[..]) && matches!(head.get(8..12), Some(b"avif" | b"avis")) {
+            Some(Self::Avif)
+        } else {
+            None
+        }
+    }
+
+    /// The extension a page in this format is saved under.
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::Gif => "gif",
+            Self::WebP => "webp",
+            Self::Avif => "avif",
+        }
+    }
+
+    /// Whether `mime`, a Content-Type without its parameters, names this
+    /// format.
+    fn is_labelled(self, mime: &str) -> bool {
+        match self {
+            // `image/jpg` and `image/pjpeg` are not registered, but common.
+            Self::Jpeg => matches!(mime, "image/jpeg" | "image/jpg" | "image/pjpeg"),
+            Self::Png => mime == "image/png",
+            Self::Gif => mime == "image/gif",
+            Self::WebP => mime == "image/webp",
+            Self::Avif => mime == "image/avif",
+        }
+    }
+
+    /// Whether the image crate, as built here, can decode this format. AVIF
+    /// needs a C decoder it is not built with.
+    fn decodes(self) -> bool {
+        self != Self::Avif
+    }
+}
+
+impl std::fmt::Display for PageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Jpeg => "JPEG",
+            Self::Png => "PNG",
+            Self::Gif => "GIF",
+            Self::WebP => "WebP",
+            Self::Avif => "AVIF",
+        })
+    }
+}
+
+/// Whether `head` — the first `IMAGE_HEAD_LEN` bytes of a response — opens with
+/// the signature of one of the formats in `PageFormat`.
 fn looks_like_image(head: &[u8]) -> bool {
-    head.starts_with(b"\xFF\xD8\xFF")                                       // JPEG
-        || (head.starts_with(b"RIFF") && head.get(8..12) == Some(&b"WEBP"[..])) // WebP
+    PageFormat::sniff(head).is_some()
+}
+
+fn is_webp(head: &[u8]) -> bool {
+    head.starts_with(b"RIFF") && head.get(8..12) == Some(&b"WEBP"[..])
 }
 
 fn unpack_packed(
@@ -368,7 +1034,8 @@ fn unpack_packed(
     let chapter: ChapterStruct = serde_json::Deserializer::from_str(&js[start..])
         .into_iter()
         .next()
-        .transpose()?
+        .transpose()
+        .map_err(|e| AppError::ContentParsing(format!("Chapter data in unpacked script: {e}")))?
         .ok_or_else(no_json)?;
     // A chapter with no images would produce an empty .cbz that marks the
     // chapter as done forever; fail here so the user sees an error instead.
@@ -441,6 +1108,12 @@ fn decode_lz_base64(data: &str, what: &str) -> Result<String> {
         .map_err(|_| AppError::ContentParsing(format!("Failed to decode {}", what)))
 }
 
+/// Where settings kept between runs live, such as the page blocklist: the
+/// platform's config directory, `~/.config/mhg_dl_rs` on Linux.
+fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("mhg_dl_rs"))
+}
+
 /// Only the headers that are identical on *every* request belong here.
 /// Anything that describes one kind of request — `accept`, `priority`, the
 /// `sec-fetch-*` triple — is set at the call site instead: `fetch_html` sends
@@ -464,15 +1137,91 @@ fn build_client() -> Result<Client> {
         headers.insert(key, HeaderValue::from_static(value));
     }
     // Without timeouts a connection that stalls after the handshake hangs the
-    // download forever; the request timeout covers reading the response body,
-    // which is where image downloads spend their time.
+    // download forever. The request timeout is not set here: the client would
+    // apply it to the whole exchange, body included, which a page read under
+    // `--limit-rate` can outlast. The `Transport` impl applies it to each step
+    // instead.
     Ok(Client::builder()
         .default_headers(headers)
         .connect_timeout(CONNECT_TIMEOUT)
-        .timeout(REQUEST_TIMEOUT)
         .build()?)
 }
 
+/// Everything a request goes through: the transport that carries it, the
+/// job's `Throttle`, the cookie jar, and the cache `fetch_html` keeps pages
+/// in. Every request is made with `get` and sent with `send`.
+///
+/// The network side is async; `runtime` is what the rest of the program,
+/// which is not, drives it with through `block_on`.
+struct Http {
+    runtime: Runtime,
+    transport: Box<dyn Transport>,
+    throttle: Throttle,
+    cookies: CookieJar,
+    cache: HtmlCache,
+}
+
+impl Http {
+    /// A client from `build_client`, paced by `throttle`, with the cookies
+    /// kept from earlier runs and the adult gate's confirmation if
+    /// `accept_adult` is set.
+    fn new(throttle: Throttle, cache: &CacheOptions, accept_adult: bool) -> Result<Self> {
+        let mut cookies = CookieJar::load(cookies::jar_path())?;
+        cookies.accept_adult = accept_adult;
+        Ok(Http {
+            runtime: runtime()?,
+            transport: Box::new(logging::Logged(build_client()?)),
+            throttle,
+            cookies,
+            cache: HtmlCache::open(cache),
+        })
+    }
+
+    /// A GET of `url`, carrying the jar's cookies for it.
+    fn get(&self, url: &Url) -> Request {
+        let request = Request::get(url);
+        match self.cookies.header_for(url) {
+            Some(cookie) => request.header("cookie", cookie),
+            None => request,
+        }
+    }
+
+    /// Pass `parsed` through, unless it is an `AdultGated` for a page fetched
+    /// with `--accept-adult` given already: the error's advice has then been
+    /// taken, and the gate staying up is the site's doing.
+    fn gate<T>(&self, parsed: Result<T>) -> Result<T> {
+        match parsed {
+            Err(AppError::AdultGated(what)) if self.cookies.accept_adult => Err(AppError::ContentParsing(format!(
+                "{what} is still behind the adult gate with --accept-adult given"
+            ))),
+            parsed => parsed,
+        }
+    }
+
+    /// Run `future` to completion on the runtime, from code that is not
+    /// async itself.
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    /// Send `request`, made by `get`, through the throttle and keep the
+    /// cookies the answer sets. Cookies set by a redirect or an error status
+    /// are not seen: the transport only hands back the final response, and an
+    /// error status goes no further than the throttle.
+    async fn send(&self, request: &Request, notify: impl Fn(String)) -> Result<Reply> {
+        let resp = self.throttle.send(self.transport.as_ref(), request, notify).await?;
+        self.cookies.store(&resp.url, &resp.headers)?;
+        Ok(resp)
+    }
+}
+
+/// The runtime each `Http` drives its requests on. A single thread is enough:
+/// the requests spend their time waiting on the network and on the throttle,
+/// not computing.
+fn runtime() -> Result<Runtime> {
+    Ok(tokio::runtime::Builder::new_current_thread().enable_all().build()?)
+}
+
 /// Resolve `href` against `base`. Pages are joined against the site root and
 /// images against their tunnel host, but both need the same thing out of it:
 /// whatever the href carries raw comes back percent-encoded, and an href that
@@ -500,23 +1249,65 @@ fn resolve_url(href: &str) -> Result<Url> {
 /// href from ever reaching the `referer` header: every one of them has been
 /// through `resolve_url`, and percent-encoding is that function's whole point.
 /// A header value cannot carry the non-ASCII bytes the search pager writes.
-fn fetch_html(client: &Client, url: &Url, referer: &Url) -> Result<String> {
-    Ok(client
-        // Cloned rather than handed over as a string: `IntoUrl` takes a `Url`
-        // as it is and re-parses a `&str`.
-        .get(url.clone())
+///
+/// The page is answered from `http`'s cache while it is fresh for its
+/// `lifetime`, and asked for conditionally once it is stale. What it says is
+/// `parse`d, and passed through `Http::gate`, before it is kept: a page that
+/// did not parse, or showed the adult gate, is asked for again next time.
+///
+/// `notify` hears when `throttle` changes the pace.
+async fn fetch_html<T>(
+    http: &Http,
+    url: &Url,
+    referer: &Url,
+    lifetime: Lifetime,
+    parse: impl FnOnce(&str) -> Result<T>,
+    notify: impl Fn(String),
+) -> Result<T> {
+    let request = http
+        .get(url)
         .header("accept", "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8")
-        .header("cookie", "country=TW")
         .header("priority", "u=0, i")
         .header("referer", referer.as_str())
         .header("sec-fetch-dest", "document")
         .header("sec-fetch-mode", "navigate")
         .header("sec-fetch-site", "same-origin")
         .header("sec-fetch-user", "?1")
-        .header("upgrade-insecure-requests", "1")
-        .send()?
-        .error_for_status()?
-        .text()?)
+        .header("upgrade-insecure-requests", "1");
+    let stale = match http.cache.lookup(&request, lifetime) {
+        Some(Cached::Fresh(body)) => {
+            log::debug!("GET {url}: from the cache");
+            return http.gate(parse(&body));
+        }
+        Some(Cached::Stale(entry)) => Some(entry),
+        None => None,
+    };
+    let conditional = match &stale {
+        Some(entry) => entry.revalidate(request.clone()),
+        None => request.clone(),
+    };
+    let resp = http.send(&conditional, notify).await?;
+    if let Some(entry) = stale
+        && resp.status == StatusCode::NOT_MODIFIED
+    {
+        let parsed = http.gate(parse(entry.body()))?;
+        http.cache.renew(entry);
+        return Ok(parsed);
+    }
+    let headers = resp.headers.clone();
+    let body = resp.text().await?;
+    let parsed = http.gate(parse(&body))?;
+    // A gated comic page can parse, its chapter list read from the view
+    // state, but is still not the page a confirmed reader gets.
+    if !shows_adult_gate(&body) {
+        http.cache.store(&request, &headers, &body);
+    }
+    Ok(parsed)
+}
+
+/// Whether `html` is, or has in it, the adult gate's warning.
+fn shows_adult_gate(html: &str) -> bool {
+    Html::parse_document(html).select(&SEL_ADULT_GATE).next().is_some()
 }
 
 /// Ask whether to fetch the next page of search results, waiting for a single
@@ -579,15 +1370,47 @@ fn search_result_from_item(li: scraper::ElementRef<'_>) -> Option<SearchResult>
     let link = li.select(&SEL_LINK).next()?;
     let comic_id = parse_id(link.value().attr("href")?)?;
     let title = link.value().attr("title")?;
-    Some(SearchResult {
+    let mut result = SearchResult {
         title: title.to_string(),
         comic_id,
-    })
+        // Lazy-loaded covers keep the real address in `data-src`. It is
+        // protocol-relative, so it is resolved like any other link; a cover
+        // that does not resolve is just left out.
+        cover_url: link
+            .select(&SEL_IMG)
+            .next()
+            .and_then(|img| img.value().attr("data-src").or(img.value().attr("src")))
+            .and_then(|src| resolve_url(src).ok())
+            .map(String::from),
+        ..SearchResult::default()
+    };
+    for field in li.select(&SEL_RESULT_FIELDS) {
+        if let Some((label, values)) = labelled_field(field) {
+            match label.as_str() {
+                "作者" => result.authors = values,
+                "別名" => result.aliases = values,
+                _ => {}
+            }
+        }
+    }
+    if let Some(status) = li.select(&SEL_RESULT_STATUS).next() {
+        (result.status, result.last_update, result.latest_chapter) = status_line(status);
+    }
+    Some(result)
+}
+
+/// The href of the pager's "next page" link, if the page has one.
+fn next_page_href(document: &Html) -> Option<String> {
+    document
+        .select(&SEL_PAGER_LINKS)
+        .find(|a| a.text().collect::<String>().trim() == "下一頁")
+        .and_then(|a| a.value().attr("href"))
+        .map(|s| s.to_string())
 }
 
 /// Extract one page of search hits plus the href of the "next page" pager link,
 /// if the page has one. A page with no recognizable results is not an error
-/// here — `interactive_search` stops paging when it sees one, and reports the
+/// here — `collect_listing` stops paging when it sees one, and reports the
 /// search as empty only if no page yielded anything at all.
 fn parse_search_results(html: &str) -> (Vec<SearchResult>, Option<String>) {
     let document = Html::parse_document(html);
@@ -597,12 +1420,44 @@ fn parse_search_results(html: &str) -> (Vec<SearchResult>, Option<String>) {
         .filter_map(search_result_from_item)
         .collect();
 
-    let next_page = document.select(&SEL_PAGER_LINKS)
-        .find(|a| a.text().collect::<String>().trim() == "下一頁")
-        .and_then(|a| a.value().attr("href"))
-        .map(|s| s.to_string());
+    (results, next_page_href(&document))
+}
+
+/// A browse listing entry's first link to a comic. Entries also link to
+/// authors and to the latest chapter; a chapter link names the comic ID too,
+/// but its text is the chapter's, which is why the first match wins — the
+/// cover or title link always comes first.
+fn listing_result_from_item(item: scraper::ElementRef<'_>) -> Option<SearchResult> {
+    item.select(&SEL_HREF).find_map(|a| {
+        let comic_id = parse_id(a.value().attr("href")?)?;
+        let title = a
+            .value()
+            .attr("title")
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .unwrap_or_else(|| element_text(a));
+        (!title.is_empty()).then(|| SearchResult {
+            title,
+            comic_id,
+            ..SearchResult::default()
+        })
+    })
+}
 
-    (results, next_page)
+/// `parse_search_results` for the browse listings: the category pages, the
+/// update page and the ranking pages. The same comic can be listed twice on
+/// the update page, once per day it was updated on, so repeats are dropped.
+fn parse_listing(html: &str) -> (Vec<SearchResult>, Option<String>) {
+    let document = Html::parse_document(html);
+
+    let mut seen = std::collections::HashSet::new();
+    let results: Vec<SearchResult> = document
+        .select(&SEL_LISTING_ITEMS)
+        .filter_map(listing_result_from_item)
+        .filter(|r| seen.insert(r.comic_id))
+        .collect();
+
+    (results, next_page_href(&document))
 }
 
 /// The section heading of a chapter list is the nearest `h4` among its
@@ -663,33 +1518,201 @@ fn extract_chapters_with_groups(document: &Html) -> Vec<Chapter> {
     chapters
 }
 
+/// An element's text with surrounding whitespace removed.
+fn element_text(e: scraper::ElementRef<'_>) -> String {
+    e.text().collect::<String>().trim().to_string()
+}
+
+/// Split a `<strong>label：</strong>value` field into its label and values.
+///
+/// The label comes back without its colon (written inside the `strong` or
+/// after it, full-width or not) and without the "漫畫" prefix the comic page
+/// puts on some labels and the search results do not, so both read the same
+/// way. Most values are a list of links; a value written as plain text is
+/// whatever follows the label.
+fn labelled_field(field: scraper::ElementRef<'_>) -> Option<(String, Vec<String>)> {
+    let strong = field.select(&SEL_STRONG).next().map(element_text)?;
+    let raw_label = strong.trim_end_matches(['：', ':']).trim();
+    let mut values: Vec<String> = field
+        .select(&SEL_ANCHOR)
+        .map(element_text)
+        .filter(|s| !s.is_empty())
+        .collect();
+    if values.is_empty() {
+        let text = element_text(field);
+        let rest = text.strip_prefix(raw_label).unwrap_or(&text);
+        let rest = rest.trim_start_matches(['：', ':']).trim();
+        if !rest.is_empty() {
+            values.push(rest.to_string());
+        }
+    }
+    let label = raw_label.strip_prefix("漫畫").unwrap_or(raw_label);
+    Some((label.to_string(), values))
+}
+
+/// Read a status line into its status, last update date and latest chapter.
+///
+/// The line is prose — "連載中。最近於 [2024-01-01] 更新至 [ 第100話 ]。" — with
+/// the status and the date each wrapped in a `span.red` and the latest chapter
+/// as its only link.
+fn status_line(line: scraper::ElementRef<'_>) -> (Option<String>, Option<String>, Option<String>) {
+    let mut reds = line.select(&SEL_RED).map(element_text);
+    let status = reds.next().filter(|s| !s.is_empty());
+    let last_update = reds.next().filter(|s| !s.is_empty());
+    let latest_chapter = line
+        .select(&SEL_ANCHOR)
+        .next()
+        .map(element_text)
+        .filter(|s| !s.is_empty());
+    (status, last_update, latest_chapter)
+}
+
+/// Read the detail block of a comic page into `ComicMetadata`.
+///
+/// Fields are recognized by their label rather than by position: the site
+/// drops a line when it has nothing to put in it, which shifts everything after
+/// it. An unknown label is ignored, and a page without the block at all just
+/// yields the default.
+fn parse_comic_metadata(document: &Html) -> ComicMetadata {
+    let mut meta = ComicMetadata {
+        subtitle: document
+            .select(&SEL_SUBTITLE)
+            .next()
+            .map(element_text)
+            .filter(|s| !s.is_empty()),
+        ..ComicMetadata::default()
+    };
+    for field in document.select(&SEL_DETAIL_FIELDS) {
+        let Some((label, values)) = labelled_field(field) else {
+            continue;
+        };
+        match label.as_str() {
+            "出品年代" => meta.year = values.into_iter().next(),
+            "地區" => meta.region = values.into_iter().next(),
+            "劇情" => meta.genres = values,
+            "作者" => meta.authors = values,
+            "別名" => meta.aliases = values,
+            _ => {}
+        }
+    }
+    if let Some(status) = document.select(&SEL_DETAIL_STATUS).next() {
+        (meta.status, meta.last_update, meta.latest_chapter) = status_line(status);
+    }
+    meta.description = document
+        .select(&SEL_INTRO)
+        .next()
+        .map(element_text)
+        .filter(|s| !s.is_empty());
+    meta
+}
+
+/// Version of the `info` document. Bumped whenever a field is removed, renamed
+/// or changes meaning, so that scripts can refuse a layout they do not know;
+/// adding a field is not a breaking change and does not bump it.
+const INFO_SCHEMA_VERSION: u32 = 1;
+
+/// The `info` command's output: everything `Comic::new` learned about a comic,
+/// plus whether each chapter is already on disk.
+#[derive(Serialize)]
+struct ComicInfo<'a> {
+    schema_version: u32,
+    comic_id: usize,
+    title: &'a str,
+    metadata: &'a ComicMetadata,
+    /// Whether the chapters came from the page's list or from behind the
+    /// adult gate.
+    chapter_source: ChapterSource,
+    chapters: Vec<ChapterInfo<'a>>,
+}
+
+#[derive(Serialize)]
+struct ChapterInfo<'a> {
+    /// 1-based, the same number the chapter prompt accepts.
+    index: usize,
+    #[serde(flatten)]
+    chapter: &'a Chapter,
+    /// Whether the chapter's archive exists — the same test `download_chapter`
+    /// uses to skip it.
+    downloaded: bool,
+}
+
 impl Comic {
     /// Fetch a comic's landing page and build the download context around it.
     ///
-    /// `id` stays a separate argument rather than being read off `args`: it may
-    /// have come from `--search` instead of the positional URL.
-    fn new(id: usize, client: Client, args: &Args) -> Result<Self> {
+    /// The comic is identified by `id` alone, whether it came from a URL or
+    /// from a search. `tunnel` indexes `TUNNEL_CHANNELS` and is only used for
+    /// page downloads.
+    fn new(
+        id: usize,
+        http: Http,
+        output_dir: &Path,
+        tunnel: usize,
+        pages: PageOptions,
+    ) -> Result<Self> {
         let url = resolve_url(&format!("/comic/{id}"))?;
-        let res = fetch_html(&client, &url, &HOST_URL)?;
-        let (title, chapters) = Self::parse_comic_html(&res)?;
+        let (title, chapters, metadata, chapter_source) =
+            http.block_on(fetch_html(&http, &url, &HOST_URL, Lifetime::Page, Self::parse_comic_html, |notice| {
+                log::warn!("{notice}")
+            }))?;
+        if chapter_source == ChapterSource::ViewState {
+            log::info!("{title} is behind the adult gate; its chapter list was read from the page's __VIEWSTATE");
+        }
         let book_safe = sanitize(&title);
-        let book_dir = args.output_dir.join(&book_safe);
+        let book_dir = output_dir.join(&book_safe);
         Ok(Comic {
-            client,
+            http,
+            id,
             // Built from a static channel name, so this parses or the channel
             // table is wrong — a bad base is a bug here, not a page's doing,
             // and failing per image request would report it 48 times over.
-            tunnel: Url::parse(&format!("https://{}.hamreus.com", TUNNEL_CHANNELS[args.tunnel]))
+            tunnel: Url::parse(&format!("https://{}.hamreus.com", TUNNEL_CHANNELS[tunnel]))
                 .expect("TUNNEL_CHANNELS entries form valid absolute URLs"),
-            delay: Duration::from_millis(args.delay_ms),
+            pages,
             title,
+            metadata,
             chapters,
+            chapter_source,
+            progress: Progress::default(),
+            fail_on: FailOn::default(),
+            tally: Tally::default(),
             book_safe,
             book_dir,
         })
     }
 
-    fn parse_comic_html(html: &str) -> Result<(String, Vec<Chapter>)> {
+    /// Where chapter `index` is packed to; its existence is what marks the
+    /// chapter as downloaded.
+    ///
+    /// Chapter names are unique across the whole comic on manhuagui (the same
+    /// name never appears in two groups), so `group` is intentionally not part
+    /// of the file name and name collisions are not a concern.
+    fn zip_path(&self, index: usize) -> PathBuf {
+        let chap_safe = sanitize(&self.chapters[index].name);
+        self.book_dir
+            .join(format!("{}_{}.cbz", self.book_safe, chap_safe))
+    }
+
+    fn info(&self) -> ComicInfo<'_> {
+        ComicInfo {
+            schema_version: INFO_SCHEMA_VERSION,
+            comic_id: self.id,
+            title: &self.title,
+            metadata: &self.metadata,
+            chapter_source: self.chapter_source,
+            chapters: self
+                .chapters
+                .iter()
+                .enumerate()
+                .map(|(i, chapter)| ChapterInfo {
+                    index: i + 1,
+                    chapter,
+                    downloaded: self.zip_path(i).exists(),
+                })
+                .collect(),
+        }
+    }
+
+    fn parse_comic_html(html: &str) -> Result<(String, Vec<Chapter>, ComicMetadata, ChapterSource)> {
         let document = Html::parse_document(html);
         let title = document
             .select(&SEL_TITLE)
@@ -699,12 +1722,13 @@ impl Comic {
             .ok_or_else(|| AppError::ContentParsing("Could not find title".to_string()))?;
 
         let mut chapters = extract_chapters_with_groups(&document);
+        let mut source = ChapterSource::List;
 
         // A gated page ships the real chapter list in the __VIEWSTATE blob.
         // A decode failure must not abort the parse: the input may be an
         // unrelated ASP.NET view state that merely happens to use that id, and
-        // the "no chapters" error below describes the situation far better
-        // than a decoder complaint would.
+        // the errors below describe the situation far better than a decoder
+        // complaint would.
         if chapters.is_empty() {
             if let Some(decoded) = document
                 .select(&SEL_VIEWSTATE)
@@ -713,28 +1737,44 @@ impl Comic {
                 .and_then(|vs_val| decode_lz_base64(vs_val, "__VIEWSTATE").ok())
             {
                 chapters = extract_chapters_with_groups(&Html::parse_fragment(&decoded));
+                source = ChapterSource::ViewState;
             }
         }
 
         if chapters.is_empty() {
+            if document.select(&SEL_ADULT_GATE).next().is_some() {
+                return Err(AppError::AdultGated(title));
+            }
             return Err(AppError::ContentParsing(
-                "No chapters found (page layout changed or content is gated)".to_string(),
+                "No chapters found (page layout changed or content is unavailable)".to_string(),
             ));
         }
 
-        Ok((title, chapters))
+        let metadata = parse_comic_metadata(&document);
+        Ok((title, chapters, metadata, source))
     }
 
     /// The seam the chapter tests drive: fetching is `download_chapter`'s job,
     /// so everything below this line works off page text alone.
     fn parse_chapter_html(html: &str) -> Result<ChapterStruct> {
-        let caps = RE_CHAPTER_DATA
-            .captures(html)
-            .ok_or_else(|| AppError::ContentParsing("Could not parse chapter data".to_string()))?;
+        let caps = RE_CHAPTER_DATA.captures(html).ok_or_else(|| {
+            // A gated chapter shows the gate's warning where its pages would be.
+            if shows_adult_gate(html) {
+                AppError::AdultGated("This chapter".to_string())
+            } else {
+                AppError::ContentParsing("Could not parse chapter data".to_string())
+            }
+        })?;
 
         let frame = &caps[1];
-        let a: usize = caps[2].parse()?;
-        let c: usize = caps[3].parse()?;
+        // The regex only lets digits through, so a number that does not parse
+        // is one too large for any real page.
+        let number = |text: &str| {
+            text.parse::<usize>()
+                .map_err(|e| AppError::ContentParsing(format!("Packed script parameter '{text}': {e}")))
+        };
+        let a = number(&caps[2])?;
+        let c = number(&caps[3])?;
         let data_b64 = &caps[4];
 
         let data_dec = decode_lz_base64(data_b64, "base64 chapter data")?;
@@ -759,84 +1799,159 @@ impl Comic {
         join_url(&self.tunnel, &format!("/{}", joined.trim_start_matches('/')))
     }
 
-    /// Download every page of `chap` into `chapter_dir`, skipping pages that are
-    /// already there. Returns the page file names in reading order, which is what
-    /// `compress_chapter` packs.
-    fn download_images(&self, chap: &ChapterStruct, chapter_dir: &Path, bar: &ProgressBar, chapter_url: &str) -> Result<Vec<String>> {
+    /// Download every page of chapter `index`, parsed as `chap`, into
+    /// `chapter_dir`, skipping pages that are already there. Returns the page
+    /// file names in reading order, which is what `compress_chapter` packs.
+    async fn download_images(
+        &self,
+        index: usize,
+        chap: &ChapterStruct,
+        chapter_dir: &Path,
+        bar: &ProgressBar,
+        chapter_url: &str,
+    ) -> Result<Vec<String>> {
         let width = chap.files.len().saturating_sub(1).to_string().len();
         let e_str = chap.sl.e.to_string();
         let mut names = Vec::with_capacity(chap.files.len());
-        let mut needs_delay = false;
+        let mut mislabelled = Vec::new();
         for (i, file) in chap.files.iter().enumerate() {
             let file_safe = sanitize(file);
-            let fname = format!("{:0width$}_{}", i, file_safe, width = width);
-            let dst = chapter_dir.join(&fname);
-            names.push(fname);
-
-            if dst.exists() {
+            // Pages are saved under the extension of the format they turn out
+            // to be: the server's names say `.jpg.webp`, or `.jpg` for a WebP.
+            // The format is only known once a page is downloaded, so a
+            // finished one is looked for under each name it may have — and
+            // under the server's own name, which earlier versions kept.
+            let base = format!("{:0width$}_{}", i, pages::image_stem(&file_safe), width = width);
+            let done = std::iter::once(format!("{:0width$}_{}", i, file_safe, width = width))
+                .chain(PageFormat::ALL.map(|f| format!("{base}.{}", f.extension())))
+                .find(|name| chapter_dir.join(name).exists());
+            if let Some(name) = done {
+                names.push(name);
                 bar.inc(1);
                 continue;
             }
-            // Space out consecutive downloads; no delay before the first one
-            // or after the last one.
-            if needs_delay {
-                thread::sleep(rand::rng().random_range(self.delay / 2..=self.delay * 3 / 2));
-            }
             // Built here rather than above the skip: a chapter already complete
             // on disk has no reason to fail over a page URL it never requests.
             let url = self.image_url(&chap.path, file)?;
-            let mut resp = self
-                .client
-                .get(url)
-                .header("accept", "image/webp,image/apng,image/*,*/*;q=0.8")
-                .header("priority", "u=4")
-                .header("referer", chapter_url)
-                .header("sec-fetch-dest", "image")
-                .header("sec-fetch-mode", "no-cors")
-                .header("sec-fetch-site", "cross-site")
-                .query(&[("e", &e_str), ("m", &chap.sl.m)])
-                .send()?
-                .error_for_status()?;
-
-            let content_length = resp.content_length();
-            // Both checks belong inside the closure: returning `Ok` is what
-            // renames the file into place, and a truncated or non-image page
-            // under the final name would be skipped as finished by every later
-            // run — there is no second chance to notice it.
-            write_atomic(&dst, |out| {
-                // Sniff the signature before writing anything. `error_for_status`
-                // only rules out an error *status*; an anti-hotlink page served
-                // as 200 would otherwise be sealed into the .cbz as a page.
-                let mut head = Vec::with_capacity(IMAGE_HEAD_LEN);
-                resp.by_ref()
-                    .take(IMAGE_HEAD_LEN as u64)
-                    .read_to_end(&mut head)?;
-                if !looks_like_image(&head) {
-                    return Err(AppError::ContentParsing(format!(
-                        "Page {} ({}) is not an image: response starts with {:02x?}",
-                        i + 1,
-                        file,
-                        head
-                    )));
-                }
-                out.write_all(&head)?;
-                let bytes_written = head.len() as u64 + io::copy(&mut resp, out)?;
-                if let Some(expected) = content_length {
-                    if bytes_written != expected {
-                        return Err(AppError::Io(io::Error::new(
-                            io::ErrorKind::UnexpectedEof,
-                            format!("Incomplete download: expected {} bytes, got {}", expected, bytes_written),
-                        )));
+            let page = format!("Page {} ({})", i + 1, file);
+            let mut attempt = 1;
+            let fetched = loop {
+                match self.fetch_page(&url, chapter_url, &e_str, &chap.sl.m, &page, bar).await {
+                    Ok(fetched) => break fetched,
+                    // A page cut short or damaged on the way is worth asking
+                    // for again. An error status or an HTML page in place of
+                    // the image would only come back the same.
+                    Err(e @ (AppError::BadImage(_) | AppError::Io(_))) if attempt < PAGE_ATTEMPTS => {
+                        log::warn!("{e}; trying again ({attempt}/{})", PAGE_ATTEMPTS - 1);
+                        attempt += 1;
                     }
+                    Err(e) => return Err(e),
                 }
-                Ok(())
-            })?;
+            };
+            if let Some(label) = fetched.mislabelled {
+                mislabelled.push(format!("{} as {label}", i + 1));
+            }
+            let name = format!("{base}.{}", fetched.format.extension());
+            // Checked in full before anything is written: a page under its
+            // final name is skipped as finished by every later run — there is
+            // no second chance to notice it.
+            write_atomic(&chapter_dir.join(&name), |out| Ok(out.write_all(&fetched.data)?))?;
+            names.push(name);
             bar.inc(1);
-            needs_delay = true;
+            self.tally.page(fetched.data.len());
+            self.progress.emit(progress::Event::PageDownloaded {
+                chapter: index + 1,
+                index: i + 1,
+                total: chap.files.len(),
+                bytes: fetched.data.len(),
+            });
+        }
+        // Once per chapter: a server that mislabels one page mislabels them
+        // all.
+        if !mislabelled.is_empty() {
+            log::warn!(
+                "Warning: {} page(s) were served with a Content-Type that does not match their contents \
+                 and were saved under their actual format (page {})",
+                mislabelled.len(),
+                mislabelled.join(", page ")
+            );
         }
         Ok(names)
     }
 
+    /// Fetch one page and check it: the signature first, before the rest of
+    /// the body is read, then the length the server announced and the image's
+    /// own structure. `page` names the page in errors; `bar` shows the
+    /// throughput.
+    async fn fetch_page(
+        &self,
+        url: &Url,
+        chapter_url: &str,
+        e: &str,
+        m: &str,
+        page: &str,
+        bar: &ProgressBar,
+    ) -> Result<FetchedPage> {
+        let request = self
+            .http
+            .get(url)
+            .header("accept", "image/webp,image/apng,image/*,*/*;q=0.8")
+            .header("priority", "u=4")
+            .header("referer", chapter_url)
+            .header("sec-fetch-dest", "image")
+            .header("sec-fetch-mode", "no-cors")
+            .header("sec-fetch-site", "cross-site")
+            .query(&[("e", e), ("m", m)]);
+        let resp = self.http.send(&request, |notice| log::warn!("{notice}")).await?;
+
+        let content_length = resp.content_length();
+        let content_type = resp
+            .headers
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.split(';').next().unwrap_or_default().trim().to_ascii_lowercase());
+        let mut body = self.http.throttle.read(resp.body, bar);
+        // Sniff the signature before reading the rest. `error_for_status` only
+        // rules out an error *status*; an anti-hotlink page served as 200
+        // would otherwise be sealed into the .cbz as a page.
+        let mut data = Vec::with_capacity(content_length.unwrap_or(0).min(1 << 26) as usize);
+        let mut format = None;
+        loop {
+            let chunk = body.chunk().await?;
+            if let Some(chunk) = &chunk {
+                data.extend_from_slice(chunk);
+            }
+            if format.is_none() && (data.len() >= IMAGE_HEAD_LEN || chunk.is_none()) {
+                let head = &data[..data.len().min(IMAGE_HEAD_LEN)];
+                format = Some(PageFormat::sniff(head).ok_or_else(|| {
+                    AppError::ContentParsing(format!("{} is not an image: response starts with {:02x?}", page, head))
+                })?);
+            }
+            if chunk.is_none() {
+                break;
+            }
+        }
+        let format = format.expect("sniffed by the time the body ends");
+        if let Some(expected) = content_length
+            && data.len() as u64 != expected
+        {
+            return Err(AppError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Incomplete download: expected {} bytes, got {}", expected, data.len()),
+            )));
+        }
+        // Without a Content-Length, this is the only thing that notices a
+        // body cut short.
+        validate::check_image(&data, self.pages.decode_pages)
+            .map_err(|e| AppError::BadImage(format!("{page}: {e}")))?;
+        // A generic binary type says nothing either way; only a type that
+        // names some other format is a mismatch.
+        let mislabelled = content_type
+            .filter(|mime| !matches!(mime.as_str(), "" | "application/octet-stream" | "binary/octet-stream"))
+            .filter(|mime| !format.is_labelled(mime));
+        Ok(FetchedPage { data, format, mislabelled })
+    }
+
     /// Pack exactly the pages `download_images` reported, in the order it
     /// reported them.
     ///
@@ -848,13 +1963,34 @@ impl Comic {
     /// directory and sorting would fold those duplicates in — and sort them
     /// into the wrong places, since `'0' < '_'` puts `0_a.webp` after
     /// `09_a.webp` — producing a scrambled .cbz that is then cached forever.
+    ///
+    /// The chapter directory is removed once the archive is in place.
     fn compress_chapter(chapter_dir: &Path, file_names: &[String], zip_path: &Path) -> Result<()> {
+        Self::write_archive(chapter_dir, file_names, zip_path)?;
+        // The .cbz is already in place; failing to clean up the now-redundant
+        // image directory must not report the chapter as failed. Warn instead.
+        if let Err(e) = fs::remove_dir_all(chapter_dir) {
+            log::warn!(
+                "Warning: failed to remove temporary directory {}: {}",
+                chapter_dir.display(),
+                e
+            );
+        }
+        Ok(())
+    }
+
+    /// Pack `file_names`, relative to `chapter_dir`, into `zip_path`. A name
+    /// may lead into a subdirectory — `PageOptions::apply` writes rewritten
+    /// pages into one — but the entry is the file name alone, so readers see
+    /// the flat list of pages they always have.
+    fn write_archive(chapter_dir: &Path, file_names: &[String], zip_path: &Path) -> Result<()> {
         write_atomic(zip_path, |zip_file| {
             let mut zip = ZipWriter::new(zip_file);
             let options = FileOptions::default().compression_method(CompressionMethod::Stored);
 
             for name in file_names {
-                zip.start_file(name.as_str(), options)?;
+                let entry = Path::new(name).file_name().and_then(|n| n.to_str()).unwrap_or(name);
+                zip.start_file(entry, options)?;
                 let mut file = fs::File::open(chapter_dir.join(name))?;
                 io::copy(&mut file, &mut zip)?;
             }
@@ -865,47 +2001,40 @@ impl Comic {
             // `write_atomic`.
             zip.finish()?;
             Ok(())
-        })?;
-        // The .cbz is already in place; failing to clean up the now-redundant
-        // image directory must not report the chapter as failed. Warn instead.
-        if let Err(e) = fs::remove_dir_all(chapter_dir) {
-            eprintln!(
-                "Warning: failed to remove temporary directory {}: {}",
-                chapter_dir.display(),
-                e
-            );
-        }
-        Ok(())
+        })
     }
 
-    fn download_chapter(&self, index: usize) -> Result<bool> {
+    /// Download chapter `index` into its archive, advancing `bar` page by page.
+    /// The bar's length is set once the chapter page has said how many pages
+    /// there are. Returns `Ok(false)`, without touching the network or the bar,
+    /// when the archive already exists.
+    fn download_chapter(&self, index: usize, bar: &ProgressBar) -> Result<bool> {
         let Chapter { name, href, .. } = &self.chapters[index];
         let chap_safe = sanitize(name);
-        // Chapter names are unique across the whole comic on manhuagui (the
-        // same name never appears in two groups), so `group` is intentionally
-        // not part of the file name and name collisions are not a concern.
-        let zip_path = self
-            .book_dir
-            .join(format!("{}_{}.cbz", self.book_safe, chap_safe));
+        let zip_path = self.zip_path(index);
         if zip_path.exists() {
-            println!("{} already exists, skipping.", zip_path.display());
             return Ok(false);
         }
         let chapter_url = resolve_url(href)?;
-        let html = fetch_html(&self.client, &chapter_url, &HOST_URL)?;
-        let chap = Self::parse_chapter_html(&html)?;
+        let chap = self.http.block_on(fetch_html(
+            &self.http,
+            &chapter_url,
+            &HOST_URL,
+            Lifetime::Chapter,
+            Self::parse_chapter_html,
+            |notice| log::warn!("{notice}"),
+        ))?;
         let chapter_dir = self.book_dir.join(&chap_safe);
         fs::create_dir_all(&chapter_dir)?;
-        let bar = ProgressBar::new(chap.files.len() as u64);
-        bar.set_style(BAR_STYLE.clone());
-        bar.set_message(name.clone());
+        bar.set_length(chap.files.len() as u64);
+        self.progress.emit(progress::Event::ChapterStarted { chapter: index + 1, name, pages: chap.files.len() });
 
         // Release the bar's draw state before compressing, not after. indicatif
         // draws to stderr, so anything written while the bar still owns its line
         // lands on top of it — and `compress_chapter` warns there when it cannot
         // remove the page directory. The bar has reached its final position by
         // this point either way, so there is nothing left for it to draw.
-        let names = match self.download_images(&chap, &chapter_dir, &bar, chapter_url.as_str()) {
+        let names = match self.http.block_on(self.download_images(index, &chap, &chapter_dir, bar, chapter_url.as_str())) {
             Ok(names) => {
                 bar.finish();
                 names
@@ -916,7 +2045,21 @@ impl Comic {
                 return Err(e);
             }
         };
-        Self::compress_chapter(&chapter_dir, &names, &zip_path)?;
+        let mut packed = self.pages.apply(&chapter_dir, names.clone())?;
+        // Packed first, before `compress_chapter` removes the pages: an
+        // originals archive that failed to write fails the chapter with
+        // everything still on disk for the next run.
+        if self.pages.keep_originals && packed != names {
+            let originals = self.book_dir.join("originals").join(zip_path.file_name().unwrap_or_default());
+            fs::create_dir_all(self.book_dir.join("originals"))?;
+            Self::write_archive(&chapter_dir, &names, &originals)?;
+        }
+        if let Some(direction) = self.pages.direction {
+            fs::write(chapter_dir.join(pages::COMIC_INFO), pages::comic_info(&self.title, name, direction))?;
+            packed.push(pages::COMIC_INFO.to_string());
+        }
+        Self::compress_chapter(&chapter_dir, &packed, &zip_path)?;
+        self.progress.emit(progress::Event::ChapterPackaged { chapter: index + 1, name, path: &zip_path });
         Ok(true)
     }
 }
@@ -994,142 +2137,740 @@ fn parse_chapter_selection(input: &str, chapters_count: usize) -> Option<Vec<usi
     Some(indices)
 }
 
-fn prompt_for_chapters<R: io::BufRead>(reader: &mut R, chapters_count: usize) -> Result<Vec<usize>> {
-    prompt_until_valid(
-        reader,
-        "Select chapters (e.g. 1-3,5): ",
-        &format!(
-            "Invalid selection. Please enter numbers between 1 and {} (e.g. 1-3,5).",
-            chapters_count
-        ),
-        |input| parse_chapter_selection(input, chapters_count),
-    )
+/// The chapters whose names match `input` when it is a `/REGEX` (the closing
+/// slash is optional). `None` when it is not one, does not compile, or
+/// matches nothing.
+fn parse_chapter_pattern(input: &str, chapters: &[Chapter]) -> Option<Vec<usize>> {
+    let pattern = input.strip_prefix('/')?;
+    let pattern = pattern.strip_suffix('/').unwrap_or(pattern);
+    let re = Regex::new(pattern).ok()?;
+    let indices: Vec<usize> = (0..chapters.len())
+        .filter(|&i| re.is_match(&chapters[i].name))
+        .collect();
+    (!indices.is_empty()).then_some(indices)
 }
 
-/// Search for `keyword`, page through the results interactively, and let the
-/// user pick a comic. Returns the selected comic's ID.
-fn interactive_search<R: io::BufRead>(
-    client: &Client,
-    reader: &mut R,
-    keyword: &str,
-) -> Result<usize> {
+/// Ask for chapters by number, or by name with a `/REGEX`. A pattern's
+/// matches are listed and confirmed before they are returned; declining asks
+/// again.
+fn prompt_for_chapters<R: io::BufRead>(reader: &mut R, chapters: &[Chapter]) -> Result<Vec<usize>> {
+    let count = chapters.len();
+    loop {
+        let (indices, by_name) = prompt_until_valid(
+            reader,
+            "Select chapters (e.g. 1-3,5, or /NAME-REGEX): ",
+            &format!(
+                "Invalid selection. Please enter numbers between 1 and {count} (e.g. 1-3,5), \
+                 or a /NAME-REGEX that matches at least one chapter."
+            ),
+            |input| match parse_chapter_pattern(input, chapters) {
+                Some(indices) => Some((indices, true)),
+                None => parse_chapter_selection(input, count).map(|indices| (indices, false)),
+            },
+        )?;
+        if !by_name {
+            return Ok(indices);
+        }
+        print_chapters(chapters, indices.iter().copied());
+        let answer = prompt_line(reader, &format!("Download these {} chapters? [Y/n] ", indices.len()))?;
+        if !answer.to_lowercase().starts_with('n') {
+            return Ok(indices);
+        }
+    }
+}
+
+/// Something that pages through comics: a keyword search or a browse listing.
+struct Listing {
+    /// What the user asked for, for messages and the JSON document.
+    label: String,
+    first_page: Url,
+    parse: fn(&str) -> (Vec<SearchResult>, Option<String>),
+}
+
+impl Listing {
+    fn search(keyword: &str) -> Result<Self> {
+        Ok(Listing {
+            label: keyword.to_string(),
+            first_page: resolve_url(&format!("/s/{}.html", urlencoding::encode(keyword)))?,
+            parse: parse_search_results,
+        })
+    }
+
+    /// A browse listing at `path` on the site.
+    fn browse(label: String, path: &str) -> Result<Self> {
+        Ok(Listing {
+            label,
+            first_page: resolve_url(path)?,
+            parse: parse_listing,
+        })
+    }
+}
+
+/// Fetch the pages of `listing`, starting from the first one.
+///
+/// `on_page` sees every page's results, together with the offset of the page's
+/// first hit among all of them and whether the pager offers a next page. When
+/// it does, `on_page` decides: `Ok(true)` fetches it, `Ok(false)` stops. This
+/// is the one place that walks a pager, whether a person or a page count is
+/// deciding.
+///
+/// Each page is narrowed and ordered by `filter` before `on_page` sees it.
+async fn collect_listing(
+    http: &Http,
+    listing: &Listing,
+    filter: &ResultFilter,
+    mut on_page: impl FnMut(usize, &[SearchResult], bool) -> Result<bool>,
+) -> Result<Vec<SearchResult>> {
     let mut all_results: Vec<SearchResult> = Vec::new();
     let mut referer = HOST_URL.clone();
-    let mut next_url = Some(resolve_url(&format!(
-        "/s/{}.html",
-        urlencoding::encode(keyword)
-    ))?);
-
-    println!("Search results for '{}':", keyword);
+    let mut next_url = Some(listing.first_page.clone());
 
     while let Some(url) = next_url {
+        let parse = |html: &str| Ok((listing.parse)(html));
         let (page_results, maybe_next) =
-            parse_search_results(&fetch_html(client, &url, &referer)?);
+            fetch_html(http, &url, &referer, Lifetime::Page, parse, |notice| log::warn!("{notice}")).await?;
         referer = url;
-        let offset = all_results.len();
-        for (i, r) in page_results.iter().enumerate() {
-            println!("{}. {}", offset + i + 1, r.title);
-        }
         // A page that parsed to no results ends the paging, whatever its pager
         // offers: a genuine "no hits" page has nothing to page through, and if
         // the result selector has instead gone stale against a redesign, every
         // page parses to nothing — without this the pager would keep asking the
         // user to press SPACE for another screen of nothing. Breaking keeps
-        // whatever earlier pages did parse; the check below turns a search that
-        // parsed nothing at all into `NoSearchResults`.
+        // whatever earlier pages did parse; the check below turns a listing
+        // that parsed nothing at all into `NoSearchResults`.
         if page_results.is_empty() {
             break;
         }
-        all_results.extend(page_results);
+        // Filtered only now: a page whose hits were all filtered out is not the
+        // end of the listing.
+        let offset = all_results.len();
+        all_results.extend(page_results.into_iter().filter(|r| filter.keeps(r)));
+        filter.sort(&mut all_results[offset..]);
 
+        let wants_next = on_page(offset, &all_results[offset..], maybe_next.is_some())?;
         next_url = match maybe_next {
-            Some(href) => {
-                if prompt_for_next_page()? {
-                    Some(resolve_url(&href)?)
-                } else {
-                    None
-                }
-            }
-            None => None,
+            Some(href) if wants_next => Some(resolve_url(&href)?),
+            _ => None,
         };
     }
 
     if all_results.is_empty() {
-        return Err(AppError::NoSearchResults(keyword.to_string()));
+        return Err(AppError::NoSearchResults(listing.label.clone()));
     }
+    Ok(all_results)
+}
+
+/// Print one page of results, numbered from `offset + 1`.
+fn print_search_page(offset: usize, page: &[SearchResult]) {
+    for (i, r) in page.iter().enumerate() {
+        println!("{}. {}", offset + i + 1, r.describe());
+    }
+}
+
+/// Page through `listing` interactively and let the user pick a comic.
+/// Returns the selected comic's ID.
+fn interactive_pick<R: io::BufRead>(
+    http: &Http,
+    reader: &mut R,
+    listing: &Listing,
+    filter: &ResultFilter,
+) -> Result<usize> {
+    println!("Results for '{}':", listing.label);
+    let all_results = http.block_on(collect_listing(http, listing, filter, |offset, page, has_next| {
+        print_search_page(offset, page);
+        if has_next {
+            prompt_for_next_page()
+        } else {
+            Ok(false)
+        }
+    }))?;
 
     let selected = prompt_for_comic_selection(reader, all_results.len())?;
     Ok(all_results[selected].comic_id)
 }
 
+/// `interactive_pick` on the full screen: the result list grows a page at a
+/// time for as long as the user asks for more. `None` if the user left the
+/// list without picking a comic.
+fn tui_pick(http: &Http, listing: &Listing, filter: &ResultFilter) -> Result<Option<usize>> {
+    let mut picker = tui::ResultPicker::new(format!("Results for '{}':", listing.label))?;
+    let mut shown = Vec::new();
+    let mut picked = None;
+    let mut cancelled = false;
+    http.block_on(collect_listing(http, listing, filter, |_, page, has_next| {
+        shown.extend_from_slice(page);
+        if shown.is_empty() && !has_next {
+            return Ok(false);
+        }
+        match picker.choose(&shown, has_next)? {
+            tui::ResultChoice::Picked(i) => {
+                picked = Some(i);
+                Ok(false)
+            }
+            tui::ResultChoice::MorePages => Ok(true),
+            tui::ResultChoice::Cancelled => {
+                cancelled = true;
+                Ok(false)
+            }
+        }
+    }))?;
+    if cancelled {
+        return Ok(None);
+    }
+    // Without a pick, the listing ran out under a filter that kept nothing.
+    let i = picked.ok_or_else(|| AppError::NoSearchResults(listing.label.clone()))?;
+    Ok(Some(shown[i].comic_id))
+}
+
+/// Fetch up to `max_pages` pages of `listing` without asking, ordered as a
+/// whole by `filter`. With `enough` set, paging also stops as soon as that
+/// many hits are in — `--pick 3` has no use for a fifth page — unless the
+/// order is not the site's, when a later page may hold what sorts first.
+fn unattended_listing(
+    http: &Http,
+    listing: &Listing,
+    filter: &ResultFilter,
+    max_pages: usize,
+    enough: Option<usize>,
+) -> Result<Vec<SearchResult>> {
+    let enough = enough.filter(|_| matches!(filter.order, ResultOrder::Site));
+    let mut pages = 0;
+    let mut results = http.block_on(collect_listing(http, listing, filter, |offset, page, _| {
+        pages += 1;
+        let have = offset + page.len();
+        Ok(pages < max_pages && enough.is_none_or(|n| have < n))
+    }))?;
+    filter.sort(&mut results);
+    Ok(results)
+}
+
+/// Version of the `--json` listing document; bumped on the same terms as
+/// `INFO_SCHEMA_VERSION`.
+const SEARCH_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct SearchDocument<'a> {
+    schema_version: u32,
+    /// The search keyword; absent for a browse listing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keyword: Option<&'a str>,
+    /// The first page of the listing.
+    url: &'a str,
+    results: &'a [SearchResult],
+}
+
+/// Pick a comic from `listing` the way `pick` says to — print them all, take
+/// the Nth, or ask — and download chapters from it, all over `http`.
+fn pick_and_download(
+    http: Http,
+    listing: Listing,
+    keyword: Option<&str>,
+    pick: PickOpts,
+    download: DownloadOpts,
+) -> Result<()> {
+    let mut stdin = io::stdin().lock();
+    let chosen = pick.pick.or(pick.first.then_some(1));
+    let filter = pick.filter();
+    let id = if pick.json {
+        let results = unattended_listing(&http, &listing, &filter, pick.max_pages, None)?;
+        let mut stdout = io::stdout().lock();
+        serde_json::to_writer_pretty(
+            &mut stdout,
+            &SearchDocument {
+                schema_version: SEARCH_SCHEMA_VERSION,
+                keyword,
+                url: listing.first_page.as_str(),
+                results: &results,
+            },
+        )?;
+        writeln!(stdout)?;
+        return Ok(());
+    } else if let Some(n) = chosen {
+        let results = unattended_listing(&http, &listing, &filter, pick.max_pages, Some(n))?;
+        let hit = results.get(n - 1).ok_or_else(|| {
+            AppError::InvalidSelection(format!(
+                "result {} (found {} in {} page(s) at most)",
+                n,
+                results.len(),
+                pick.max_pages
+            ))
+        })?;
+        if !download.progress.is_json() {
+            println!("Picked {}. {}", n, hit.describe());
+        }
+        hit.comic_id
+    } else if download.use_tui() {
+        match tui_pick(&http, &listing, &filter)? {
+            Some(id) => id,
+            // Quitting the list is a choice too: to download nothing.
+            None => return Ok(()),
+        }
+    } else {
+        interactive_pick(&http, &mut stdin, &listing, &filter)?
+    };
+    let comic = open_comic_by_id(http, id, &download)?;
+    select_and_download(&comic, &pick.select, download.use_tui(), &mut stdin)
+}
+
 /// `main` deliberately does not return `Result`: the `Termination` impl for
 /// `Result<T, E>` reports the error with `Debug`, which would print
 /// `NoSearchResults("金田一")` instead of the `#[error(...)]` text every
-/// `AppError` variant carries. Report `Display` here and hand back a plain
-/// exit code — this is the only place a user-facing error is printed.
+/// `AppError` variant carries. Report `Display` here and hand back the
+/// error's exit code — this is the only place a user-facing error is printed.
 fn main() -> ExitCode {
     match run() {
         Ok(()) => ExitCode::SUCCESS,
         Err(e) => {
-            eprintln!("Error: {e}");
-            ExitCode::FAILURE
+            log::error!("Error: {e}");
+            ExitCode::from(e.exit_code())
         }
     }
 }
 
-fn run() -> Result<()> {
-    let args = Args::parse();
-    let client = build_client()?;
-    let mut stdin = io::stdin().lock();
+/// Fetch the comic `url` names, with the download options it will be
+/// downloaded under.
+fn open_comic(url: &str, opts: &DownloadOpts) -> Result<Comic> {
+    let id = parse_id(url).ok_or(AppError::InvalidUrl)?;
+    open_comic_by_id(opts.http()?, id, opts)
+}
 
-    let id = if let Some(ref search_keyword) = args.search {
-        interactive_search(&client, &mut stdin, search_keyword)?
-    } else {
-        let url = args
-            .url
-            .as_deref()
-            .expect("clap's required_unless_present guarantees url when search is absent");
-        parse_id(url).ok_or(AppError::InvalidUrl)?
-    };
+/// `open_comic` for a comic already identified, over an `http` that may have
+/// served the listing it was picked from.
+fn open_comic_by_id(http: Http, id: usize, opts: &DownloadOpts) -> Result<Comic> {
+    // Read before the comic page is fetched: a blocklist that does not parse
+    // is better reported before any downloading than after it.
+    let mut pages = opts.pages.clone();
+    pages.load_blocklist()?;
+    let mut comic = Comic::new(
+        id,
+        http,
+        &opts.output_dir,
+        opts.tunnel,
+        pages,
+    )?;
+    comic.progress = opts.progress;
+    comic.fail_on = opts.fail_on;
+    comic.progress.emit(progress::Event::ComicLoaded { id, title: &comic.title, chapters: comic.chapters.len() });
+    Ok(comic)
+}
 
-    let comic = Comic::new(id, client, &args)?;
+/// Print the title and the numbered chapter list the chapter prompt refers to.
+fn print_chapter_list(comic: &Comic) {
     println!("Title: {}", comic.title);
-    let mut last_group = "";
-    for (i, chapter) in comic.chapters.iter().enumerate() {
-        if chapter.group != last_group {
+    print_chapters(&comic.chapters, 0..comic.chapters.len());
+}
+
+/// Print the chapters at `indices`, numbered as the prompt numbers them and
+/// under their group headings.
+fn print_chapters(chapters: &[Chapter], indices: impl IntoIterator<Item = usize>) {
+    let mut last_group = None;
+    for i in indices {
+        let chapter = &chapters[i];
+        if last_group != Some(&chapter.group) {
             println!("{}:", chapter.group);
-            last_group = &chapter.group;
+            last_group = Some(&chapter.group);
         }
         println!("  {}: {}", i + 1, chapter.name);
     }
+}
+
+/// Download the chapters `select` names, or list them and ask when it names
+/// none. With `tui` set, the asking and the download both happen on the full
+/// screen.
+fn select_and_download<R: io::BufRead>(
+    comic: &Comic,
+    select: &ChapterOpts,
+    tui: bool,
+    reader: &mut R,
+) -> Result<()> {
+    let count = comic.chapters.len();
+    let indices = if select.chapters.is_some() || !select.names.is_empty() {
+        let numbered = match &select.chapters {
+            Some(selection) => parse_chapter_selection(selection, count).ok_or_else(|| {
+                AppError::InvalidSelection(format!("chapters '{selection}' (the comic has {count})"))
+            })?,
+            None => (0..count).collect(),
+        };
+        let indices: Vec<usize> = numbered
+            .into_iter()
+            .filter(|&i| select.names.keeps(&comic.chapters[i]))
+            .collect();
+        if indices.is_empty() {
+            return Err(AppError::InvalidSelection(
+                "no chapter matches the given selection".to_string(),
+            ));
+        }
+        // Which chapters a pattern caught is not obvious from the pattern, so
+        // show them before the first request goes out.
+        if !select.names.is_empty() && !comic.progress.is_json() {
+            println!("{}: {} of {} chapters match", comic.title, indices.len(), count);
+            print_chapters(&comic.chapters, indices.iter().copied());
+        }
+        indices
+    } else if tui {
+        tui::pick_chapters(comic)?
+    } else {
+        print_chapter_list(comic);
+        prompt_for_chapters(reader, &comic.chapters)?
+    };
+    if tui && !indices.is_empty() {
+        return tui::download_view(comic, &indices);
+    }
+    download_chapters(comic, &indices)
+}
 
-    let indices = prompt_for_chapters(&mut stdin, comic.chapters.len())?;
+/// Run `download_chapter` over `indices` in order. `bar_for` supplies each
+/// chapter's progress bar, and `report` hears how the chapter went; returning
+/// `false` from it stops the run. A failed chapter does not stop it by itself.
+/// Returns how each chapter went, for the summary.
+///
+/// There is no pause between chapters here: every request waits its turn with
+/// the comic's `Throttle`, which paces a chapter's pages and the next
+/// chapter's page alike, whatever is showing the download.
+fn download_each(
+    comic: &Comic,
+    indices: &[usize],
+    mut bar_for: impl FnMut(usize) -> ProgressBar,
+    mut report: impl FnMut(usize, Result<bool>) -> bool,
+) -> Summary {
+    let mut summary = Summary::new();
+    for &idx in indices {
+        let bar = bar_for(idx);
+        let result = comic.download_chapter(idx, &bar);
+        summary.record(idx, &comic.chapters[idx].name, &result);
+        // A bar the chapter never got to use — skipped, or failed before its
+        // page count was known — would draw itself as an empty 0/0 bar when
+        // dropped.
+        if !bar.is_finished() {
+            bar.finish_and_clear();
+        }
+        if !report(idx, result) {
+            break;
+        }
+    }
+    summary
+}
 
-    for (k, &idx) in indices.iter().enumerate() {
-        // Whether to pause before the next chapter: after an actual download,
-        // or after an error (to avoid hammering the server on a connection
-        // issue). A skipped, already-present chapter needs no pause.
-        let should_pause = match comic.download_chapter(idx) {
-            Ok(downloaded) => downloaded,
-            Err(e) => {
+/// Download `indices` one after another with a progress bar each on stderr,
+/// or with events on stdout under `--progress json`. A failed chapter is
+/// reported and the rest carry on; the summary at the end says whether the
+/// run failed.
+fn download_chapters(comic: &Comic, indices: &[usize]) -> Result<()> {
+    let summary = download_each(
+        comic,
+        indices,
+        |idx| {
+            if comic.progress.is_json() {
+                return ProgressBar::hidden();
+            }
+            logging::track(
+                ProgressBar::new(0)
+                    .with_style(BAR_STYLE.clone())
+                    .with_message(comic.chapters[idx].name.clone()),
+            )
+        },
+        |idx, result| {
+            let name = &comic.chapters[idx].name;
+            match result {
+                Ok(true) => {}
+                Ok(false) if comic.progress.is_json() => {
+                    let path = comic.zip_path(idx);
+                    comic.progress.emit(progress::Event::ChapterSkipped { chapter: idx + 1, name, path: &path });
+                }
+                Ok(false) => log::info!("{} already exists, skipping.", comic.zip_path(idx).display()),
                 // Named as well as numbered: a selection like "1-3,50" reports
                 // failures out of order and far apart, and the number alone
                 // means scrolling back to the listing to find out what broke.
-                eprintln!(
-                    "Failed to download chapter {} ({}): {}",
-                    idx + 1,
-                    comic.chapters[idx].name,
-                    e
-                );
-                true
+                Err(e) => {
+                    log::error!("Failed to download chapter {} ({}): {}", idx + 1, name, e);
+                    comic.progress.emit(progress::Event::ChapterFailed { chapter: idx + 1, name, error: e.to_string() });
+                }
             }
+            true
+        },
+    );
+    finish_run(comic, &summary)
+}
+
+/// Show `summary` the way `comic`'s progress is shown, and say whether the
+/// run failed.
+fn finish_run(comic: &Comic, summary: &Summary) -> Result<()> {
+    if comic.progress.is_json() {
+        comic.progress.emit(summary.event(&comic.tally));
+    } else {
+        for line in summary.lines(&comic.tally) {
+            println!("{line}");
+        }
+    }
+    summary.verdict(comic.fail_on)
+}
+
+/// Print `Comic::info` for one comic as pretty JSON on stdout.
+fn print_info(http: Http, url: &str, output_dir: &Path) -> Result<()> {
+    let id = parse_id(url).ok_or(AppError::InvalidUrl)?;
+    let comic = Comic::new(id, http, output_dir, 0, PageOptions::default())?;
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, &comic.info())?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// The book directories under `output_dir`, sorted by name. A missing output
+/// directory is simply an empty library.
+fn book_dirs(output_dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(output_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// The finished archives in one book directory, sorted by name. `.part` files
+/// are left out: they are not archives yet.
+fn archives_in(book_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut archives = Vec::new();
+    for entry in fs::read_dir(book_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "cbz") {
+            archives.push(path);
+        }
+    }
+    archives.sort();
+    Ok(archives)
+}
+
+/// Page `n` of the archive at `path`, counting from 1 or, when negative, back
+/// from the last page, with its entry name. ComicInfo.xml is not a page.
+fn archive_page(path: &Path, n: isize) -> Result<(Vec<u8>, String)> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+    let mut entries = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        if archive.by_index_raw(i)?.name() != pages::COMIC_INFO {
+            entries.push(i);
+        }
+    }
+    let index = match n {
+        1.. => entries.get(n as usize - 1),
+        ..0 => entries.len().checked_sub(n.unsigned_abs()).and_then(|i| entries.get(i)),
+        0 => None,
+    }
+    .ok_or_else(|| {
+        AppError::InvalidSelection(format!("page {} of {} ({} pages)", n, path.display(), entries.len()))
+    })?;
+    let mut entry = archive.by_index(*index)?;
+    // Capped as in `verify_archive`: the declared size may be damaged.
+    let mut data = Vec::with_capacity(entry.size().min(1 << 26) as usize);
+    entry.read_to_end(&mut data)?;
+    Ok((data, entry.name().to_string()))
+}
+
+/// Hash each of `files` — an image, or page `page` of an archive — onto the
+/// blocklist.
+fn block_pages(files: &[PathBuf], page: Option<isize>, note: Option<&str>, blocklist: Option<&Path>) -> Result<()> {
+    let list = pages::blocklist_path(blocklist)?;
+    for file in files {
+        let (data, source) = if file.extension().is_some_and(|ext| ext == "cbz") {
+            let n = page.ok_or_else(|| {
+                AppError::InvalidSelection(format!("{} is an archive; say which page with --page N", file.display()))
+            })?;
+            let (data, entry) = archive_page(file, n)?;
+            (data, format!("{} {}", file.display(), entry))
+        } else {
+            (fs::read(file)?, file.display().to_string())
+        };
+        let entry = pages::BlockEntry {
+            hash: pages::page_hash(&image::load_from_memory(&data)?),
+            note: note.map_or(source, str::to_string),
         };
-        if should_pause && k + 1 < indices.len() {
-            thread::sleep(Duration::from_secs(5));
+        pages::Blocklist::append(&list, &entry)?;
+        println!("Blocked {entry}");
+    }
+    println!("Blocklist: {}", list.display());
+    Ok(())
+}
+
+/// Merge the site's cookies from the browser export `file` into the jar every
+/// later run sends them from.
+fn import_cookies(file: &Path) -> Result<()> {
+    let path = cookies::jar_path().ok_or_else(|| {
+        AppError::Io(io::Error::new(io::ErrorKind::NotFound, "no config directory to keep cookies in"))
+    })?;
+    let jar = CookieJar::load(Some(path.clone()))?;
+    match jar.import(&cookies::read_export(file)?)? {
+        0 => println!("{} has no cookies for the site or its image hosts", file.display()),
+        n => println!("Imported {} cookie(s) into {}", n, path.display()),
+    }
+    Ok(())
+}
+
+fn list_library(output_dir: &Path) -> Result<()> {
+    let books = book_dirs(output_dir)?;
+    if books.is_empty() {
+        println!("No comics in {}", output_dir.display());
+    }
+    for book in books {
+        let count = archives_in(&book)?.len();
+        let name = book.file_name().unwrap_or_default().to_string_lossy();
+        println!("{name} ({count} chapters)");
+    }
+    Ok(())
+}
+
+/// Read every entry of one archive to the end, which is what makes the zip
+/// reader check its CRC, and sniff each one for an image signature. Returns
+/// the first problem found.
+fn verify_archive(path: &Path) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+    if archive.is_empty() {
+        return Err(AppError::ContentParsing("archive has no pages".to_string()));
+    }
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        // The one entry that is not a page, written with `--direction`.
+        if entry.name() == pages::COMIC_INFO {
+            continue;
+        }
+        // The size is the archive's word for it, and a damaged archive's word
+        // is what is being checked: reserve no more than a page could need.
+        let mut data = Vec::with_capacity(entry.size().min(1 << 26) as usize);
+        entry.read_to_end(&mut data)?;
+        if !looks_like_image(&data[..data.len().min(IMAGE_HEAD_LEN)]) {
+            return Err(AppError::ContentParsing(format!(
+                "{} is not an image",
+                entry.name()
+            )));
+        }
+        validate::check_image(&data, false)
+            .map_err(|e| AppError::BadImage(format!("{}: {}", entry.name(), e)))?;
+    }
+    Ok(())
+}
+
+/// Verify the archives of the named book directories, or of every one when
+/// none is named.
+fn verify_library(output_dir: &Path, comics: &[String], delete: bool) -> Result<()> {
+    let books = if comics.is_empty() {
+        book_dirs(output_dir)?
+    } else {
+        comics.iter().map(|c| output_dir.join(c)).collect()
+    };
+    let mut failed = 0;
+    for book in books {
+        for archive in archives_in(&book)? {
+            match verify_archive(&archive) {
+                Ok(()) => println!("OK      {}", archive.display()),
+                Err(e) => {
+                    failed += 1;
+                    println!("BROKEN  {}: {}", archive.display(), e);
+                    if delete {
+                        fs::remove_file(&archive)?;
+                    }
+                }
+            }
         }
     }
+    if failed > 0 {
+        return Err(AppError::VerifyFailed(failed));
+    }
     Ok(())
 }
 
+fn run() -> Result<()> {
+    let args = Args::parse();
+    logging::init(&args.log)?;
+    let command = match (args.command, args.search) {
+        (Some(command), _) => command,
+        (None, Some(keyword)) => Command::Search { keyword, pick: PickOpts::default(), download: args.download },
+        (None, None) => Command::Download {
+            url: args
+                .url
+                .expect("clap requires URL when no subcommand or --search is given"),
+            select: ChapterOpts::default(),
+            download: args.download,
+        },
+    };
+
+    match command {
+        Command::Search { keyword, pick, download } => {
+            pick_and_download(download.http()?, Listing::search(&keyword)?, Some(&keyword), pick, download)
+        }
+        Command::Browse { listing } => {
+            let (listing, pick, download) = match listing {
+                BrowseListing::Category { region, genre, audience, status, sort, pick, download } => {
+                    let path = category_path(region, genre.as_deref(), audience, status, sort);
+                    (Listing::browse(path.clone(), &path)?, pick, download)
+                }
+                BrowseListing::Rank { period, pick, download } => {
+                    let path = rank_path(period);
+                    (Listing::browse(format!("{} ranking", slug(period)), &path)?, pick, download)
+                }
+                BrowseListing::Updates { pick, download } => {
+                    (Listing::browse("latest updates".to_string(), "/update/")?, pick, download)
+                }
+            };
+            pick_and_download(download.http()?, listing, None, pick, download)
+        }
+        Command::Info { url, output_dir, accept_adult, cache } => {
+            print_info(Http::new(Throttle::default(), &cache, accept_adult)?, &url, &output_dir)
+        }
+        Command::Download { url, select, download } => {
+            let comic = open_comic(&url, &download)?;
+            select_and_download(&comic, &select, download.use_tui(), &mut io::stdin().lock())
+        }
+        Command::Update { url, names, download } => {
+            let comic = open_comic(&url, &download)?;
+            let missing: Vec<usize> = (0..comic.chapters.len())
+                .filter(|&i| names.keeps(&comic.chapters[i]) && !comic.zip_path(i).exists())
+                .collect();
+            if !comic.progress.is_json() {
+                println!(
+                    "{}: {} of {} chapters to download",
+                    comic.title,
+                    missing.len(),
+                    comic.chapters.len()
+                );
+                // As with `download`, show which chapters a pattern caught
+                // before the first request goes out.
+                if !names.is_empty() {
+                    print_chapters(&comic.chapters, missing.iter().copied());
+                }
+            }
+            if download.use_tui() && !missing.is_empty() {
+                return tui::download_view(&comic, &missing);
+            }
+            download_chapters(&comic, &missing)
+        }
+        Command::Verify { comics, output_dir, delete } => {
+            verify_library(&output_dir, &comics, delete)
+        }
+        Command::List { output_dir } => list_library(&output_dir),
+        Command::Block { files, page, note, blocklist } => {
+            block_pages(&files, page, note.as_deref(), blocklist.as_deref())
+        }
+        Command::Cookies { file } => import_cookies(&file),
+    }
+}
+
+mod cache;
+mod cookies;
+mod logging;
+mod pages;
+mod progress;
+mod summary;
+mod throttle;
+mod transport;
+mod tui;
+mod validate;
+
+#[cfg(test)]
+mod mock_site;
 #[cfg(test)]
 mod tests;
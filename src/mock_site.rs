@@ -0,0 +1,232 @@
+//! A stand-in for the site and its image hosts, served from a local port, for
+//! tests that run whole flows rather than one parser at a time.
+//!
+//! Every host's requests reach the same local server through `MockTransport`,
+//! which puts the host they were meant for at the front of the path. A route
+//! is registered under the URL the program will ask for, and answers it in
+//! one of the ways the real hosts do — including slowly, cut short, with a
+//! throttling status, or with something that is not an image at all.
+
+use crate::{
+    cache::HtmlCache,
+    cookies::CookieJar,
+    throttle::{PaceOptions, Throttle},
+    runtime,
+    transport::{BoxFuture, Reply, Request, Transport},
+    Http, Result,
+};
+use reqwest::{Client, Url};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// How a route answers.
+#[derive(Clone)]
+pub(crate) enum Answer {
+    /// 200 with `body`, labelled `content_type`.
+    Ok { body: Vec<u8>, content_type: &'static str },
+    /// 200 with `body` sent a kilobyte at a time, `delay` apart.
+    Slow { body: Vec<u8>, delay: Duration },
+    /// 200 with a `Content-Length` for all of `body`, but the connection
+    /// closed after half of it.
+    Truncated(Vec<u8>),
+    /// 429 with `Retry-After: 0` to the first `times` requests, then `then`.
+    Throttled { times: usize, then: Box<Answer> },
+}
+
+impl Answer {
+    pub(crate) fn html(body: impl Into<String>) -> Self {
+        Answer::Ok { body: body.into().into_bytes(), content_type: "text/html; charset=utf-8" }
+    }
+
+    pub(crate) fn image(body: Vec<u8>) -> Self {
+        Answer::Ok { body, content_type: "image/jpeg" }
+    }
+}
+
+#[derive(Default)]
+struct Routes {
+    answers: HashMap<String, Answer>,
+    hits: HashMap<String, usize>,
+}
+
+/// The local server and what it answers. It runs until the test process ends.
+pub(crate) struct MockSite {
+    addr: SocketAddr,
+    routes: Arc<Mutex<Routes>>,
+}
+
+impl MockSite {
+    pub(crate) fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let routes = Arc::new(Mutex::new(Routes::default()));
+        let shared = routes.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let routes = shared.clone();
+                thread::spawn(move || serve(stream, &routes));
+            }
+        });
+        MockSite { addr, routes }
+    }
+
+    /// Answer requests for `url` with `answer`, whatever their query string.
+    pub(crate) fn route(&self, url: &str, answer: Answer) {
+        self.routes.lock().unwrap().answers.insert(route_key(&Url::parse(url).unwrap()), answer);
+    }
+
+    /// How many requests for `url` have arrived.
+    pub(crate) fn hits(&self, url: &str) -> usize {
+        let key = route_key(&Url::parse(url).unwrap());
+        self.routes.lock().unwrap().hits.get(&key).copied().unwrap_or(0)
+    }
+
+    /// An `Http` whose every request goes to this site, paced by `pace`, with
+    /// a cookie jar and cache that live for the test only.
+    pub(crate) fn http(&self, pace: &PaceOptions) -> Http {
+        Http {
+            runtime: runtime().unwrap(),
+            transport: Box::new(MockTransport { client: Client::new(), addr: self.addr }),
+            throttle: Throttle::new(pace),
+            cookies: CookieJar::default(),
+            cache: HtmlCache::default(),
+        }
+    }
+}
+
+/// Sends each request to the mock site instead of its host, and hands the
+/// answer back as if the host had given it.
+struct MockTransport {
+    client: Client,
+    addr: SocketAddr,
+}
+
+impl Transport for MockTransport {
+    fn send<'a>(&'a self, request: &'a Request) -> BoxFuture<'a, Result<Reply>> {
+        Box::pin(async move {
+            let url = &request.url;
+            let mut local = Url::parse(&format!("http://{}/{}{}", self.addr, url.host_str().unwrap(), url.path())).unwrap();
+            local.set_query(url.query());
+            let local = Request { url: local, headers: request.headers.clone() };
+            let mut reply = self.client.send(&local).await?;
+            reply.url = url.clone();
+            Ok(reply)
+        })
+    }
+}
+
+/// `host/path`, which is also the path `MockTransport` asks the server for.
+fn route_key(url: &Url) -> String {
+    format!("{}{}", url.host_str().unwrap(), url.path())
+}
+
+/// Answer the one request `stream` carries, then close it.
+fn serve(mut stream: TcpStream, routes: &Mutex<Routes>) {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => head.extend_from_slice(&buf[..n]),
+        }
+    }
+    let head = String::from_utf8_lossy(&head);
+    let target = head.split(' ').nth(1).unwrap_or("/");
+    let key = target.trim_start_matches('/').split('?').next().unwrap().to_string();
+
+    let answer = {
+        let mut routes = routes.lock().unwrap();
+        let hits = routes.hits.entry(key.clone()).or_default();
+        *hits += 1;
+        let hits = *hits;
+        match routes.answers.get(&key) {
+            Some(Answer::Throttled { times, then }) => {
+                if hits <= *times { None } else { Some((**then).clone()) }
+            }
+            Some(answer) => Some(answer.clone()),
+            None => {
+                let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+                return;
+            }
+        }
+    };
+    let Some(answer) = answer else {
+        let _ = stream.write_all(
+            b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        );
+        return;
+    };
+    let ok = |len: usize, content_type: &str| {
+        format!("HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {len}\r\nConnection: close\r\n\r\n")
+    };
+    let _ = match answer {
+        Answer::Ok { body, content_type } => {
+            stream.write_all(ok(body.len(), content_type).as_bytes()).and_then(|_| stream.write_all(&body))
+        }
+        Answer::Slow { body, delay } => stream.write_all(ok(body.len(), "image/jpeg").as_bytes()).and_then(|_| {
+            for chunk in body.chunks(1024) {
+                thread::sleep(delay);
+                stream.write_all(chunk)?;
+                stream.flush()?;
+            }
+            Ok(())
+        }),
+        Answer::Truncated(body) => stream
+            .write_all(ok(body.len(), "image/jpeg").as_bytes())
+            .and_then(|_| stream.write_all(&body[..body.len() / 2])),
+        Answer::Throttled { .. } => unreachable!("resolved above"),
+    };
+}
+
+/// A search results page listing `results`, as `(comic ID, title)`, with a
+/// pager linking to `next` if there is one.
+pub(crate) fn search_page(results: &[(usize, &str)], next: Option<&str>) -> String {
+    let items: String = results
+        .iter()
+        .map(|(id, title)| {
+            format!(
+                r#"<li class="cf"><a class="bcover" href="/comic/{id}/" title="{title}"><img src="/cover/{id}.jpg"></a></li>"#
+            )
+        })
+        .collect();
+    let pager = next
+        .map(|href| format!(r#"<div class="pager"><a href="{href}">下一頁</a></div>"#))
+        .unwrap_or_default();
+    format!(r#"<html><body><div class="book-result"><ul>{items}</ul></div>{pager}</body></html>"#)
+}
+
+/// A comic page titled `title` listing `chapters`, as `(href, name)` in
+/// reading order. The site lists them newest first.
+pub(crate) fn comic_page(title: &str, chapters: &[(&str, &str)]) -> String {
+    let items: String = chapters
+        .iter()
+        .rev()
+        .map(|(href, name)| format!(r#"<li><a href="{href}" title="{name}"><span>{name}</span></a></li>"#))
+        .collect();
+    format!(
+        r#"<html><body><div class="book-title"><h1>{title}</h1></div>
+        <h4><span>單話</span></h4><div class="chapter-list"><ul>{items}</ul></div></body></html>"#
+    )
+}
+
+/// A chapter page whose pages are `files` under `path` on the tunnel.
+///
+/// The site packs the chapter data with an LZ-compressed dictionary. This one
+/// packs it with an empty dictionary — `Q===` is the empty string compressed —
+/// so the frame is the script as it stands.
+pub(crate) fn chapter_page(path: &str, files: &[&str]) -> String {
+    let data = serde_json::json!({
+        "files": files,
+        "path": path,
+        "sl": { "e": 1_700_000_000, "m": "signature" },
+    });
+    format!(
+        r#"<html><body><script>window["\x65\x76\x61\x6c"](function(p,a,c,k,e,d){{return p;}}('SMH.imgData({data}).preInit();',62,0,'Q==='['\x73\x70\x6c\x69\x63']('\x7c'),0,{{}}))</script></body></html>"#
+    )
+}
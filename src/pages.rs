@@ -0,0 +1,562 @@
+//! What happens to a chapter's pages between downloading and packing them.
+//!
+//! `download_images` leaves each page on disk as the tunnel served it, already
+//! checked to be an image. The steps here write the rewritten pages into a
+//! subdirectory of the chapter directory and hand back the names to pack,
+//! which `compress_chapter` takes as they come. Nothing here touches the
+//! network.
+
+use crate::{config_dir, is_webp, write_atomic, AppError, PageFormat, Result};
+use image::{
+    codecs::{jpeg::JpegEncoder, png::PngEncoder},
+    imageops::FilterType,
+    DynamicImage,
+};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Quality of re-encoded JPEG pages unless `--jpeg-quality` says otherwise.
+const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+/// Where in the chapter directory rewritten pages go. Never over the page they
+/// came from: the original stays under the name `download_images` skips until
+/// the chapter directory is removed after packing, so a run interrupted
+/// halfway through a chapter neither downloads it again nor rewrites a page
+/// that was already rewritten — grayscale and gamma applied twice would not be
+/// the same page.
+const PROCESSED_DIR: &str = "processed";
+
+/// Extensions a page name may carry, in any case. The tunnel's names often
+/// stack two of them, as in `001.jpg.webp`.
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "gif", "avif"];
+
+/// What rewritten pages are encoded as.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub(crate) enum WebpTarget {
+    /// Lossy, at --jpeg-quality; much smaller than PNG for scanned pages
+    Jpeg,
+    /// Lossless
+    Png,
+}
+
+impl WebpTarget {
+    fn extension(self) -> &'static str {
+        match self {
+            WebpTarget::Jpeg => "jpg",
+            WebpTarget::Png => "png",
+        }
+    }
+}
+
+/// What is done with a double-page spread: a page wider than it is tall.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub(crate) enum Spreads {
+    /// Leave spreads as they are
+    Keep,
+    /// Cut each spread into its two pages, in reading order
+    Split,
+    /// Turn each spread on its side to fill a portrait screen
+    Rotate,
+}
+
+/// Which way a book is read, which is also the order a split spread's halves
+/// come in.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub(crate) enum Direction {
+    /// Right to left, as Japanese manga are
+    Rtl,
+    /// Left to right, as most manhua are
+    Ltr,
+}
+
+/// E-readers pages can be fitted to, by their portrait screen resolution.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub(crate) enum Device {
+    /// Kindle (2022), 1072x1448
+    Kindle,
+    /// Kindle Paperwhite (2021 and later), 1236x1648
+    KindlePaperwhite,
+    /// Kindle Oasis, 1264x1680
+    KindleOasis,
+    /// Kindle Scribe, 1860x2480
+    KindleScribe,
+    /// Kobo Clara HD, 2E and BW, 1072x1448
+    KoboClara,
+    /// Kobo Libra H2O and 2, 1264x1680
+    KoboLibra,
+    /// Kobo Sage, 1440x1920
+    KoboSage,
+    /// Kobo Elipsa, 1404x1872
+    KoboElipsa,
+}
+
+impl Device {
+    fn screen(self) -> Screen {
+        let (width, height) = match self {
+            Device::Kindle | Device::KoboClara => (1072, 1448),
+            Device::KindlePaperwhite => (1236, 1648),
+            Device::KindleOasis | Device::KoboLibra => (1264, 1680),
+            Device::KindleScribe => (1860, 2480),
+            Device::KoboSage => (1440, 1920),
+            Device::KoboElipsa => (1404, 1872),
+        };
+        Screen { width, height }
+    }
+}
+
+/// A screen size in pixels, as `--screen` takes it: `WIDTHxHEIGHT`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Screen {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+impl std::str::FromStr for Screen {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        let parsed = s
+            .split_once(['x', 'X'])
+            .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)));
+        match parsed {
+            Some((width, height)) if width > 0 && height > 0 => Ok(Screen { width, height }),
+            _ => Err(format!("expected WIDTHxHEIGHT in pixels, e.g. 1072x1448, not '{s}'")),
+        }
+    }
+}
+
+fn positive(s: &str) -> std::result::Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(v) if v > 0.0 && v.is_finite() => Ok(v),
+        _ => Err(format!("expected a number above 0, not '{s}'")),
+    }
+}
+
+/// A contrast change from -100, which flattens a page to a single gray (any
+/// lower would start to invert it), to 100, past which the mid-tones are gone.
+fn contrast(s: &str) -> std::result::Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(v) if (-100.0..=100.0).contains(&v) => Ok(v),
+        _ => Err(format!("expected a percentage from -100 to 100, not '{s}'")),
+    }
+}
+
+/// How a chapter's pages are rewritten before packing. The default leaves
+/// them exactly as downloaded.
+#[derive(Clone, Debug, clap::Args)]
+#[clap(next_help_heading = "PAGE PROCESSING")]
+pub(crate) struct PageOptions {
+    /// Re-encode WebP pages as JPEG or PNG before packing them. Pages resized
+    /// or toned by the other options are written in this format too, JPEG
+    /// unless it is set
+    #[clap(long, value_name = "FORMAT", value_enum)]
+    pub(crate) webp_to: Option<WebpTarget>,
+    /// Quality of re-encoded JPEG pages, 1-100
+    #[clap(long, value_name = "N", default_value_t = DEFAULT_JPEG_QUALITY, value_parser = clap::builder::RangedU64ValueParser::<u8>::new().range(1..=100))]
+    pub(crate) jpeg_quality: u8,
+    /// Shrink pages to fit this e-reader's screen
+    #[clap(long, value_enum, conflicts_with = "screen")]
+    pub(crate) device: Option<Device>,
+    /// Shrink pages to fit a WIDTHxHEIGHT screen
+    #[clap(long, value_name = "WxH", value_parser)]
+    pub(crate) screen: Option<Screen>,
+    /// Convert pages to grayscale
+    #[clap(long)]
+    pub(crate) grayscale: bool,
+    /// Gamma of the rewritten pages; above 1 darkens the mid-tones, which
+    /// e-ink screens tend to show washed out
+    #[clap(long, value_name = "GAMMA", default_value_t = 1.0, value_parser = positive)]
+    pub(crate) gamma: f32,
+    /// Contrast change in percent, from -100 to 100, e.g. 20 or -10
+    #[clap(long, value_name = "PERCENT", default_value_t = 0.0, allow_hyphen_values = true, value_parser = contrast)]
+    pub(crate) contrast: f32,
+    /// What to do with double-page spreads
+    #[clap(long, value_enum, default_value = "keep")]
+    pub(crate) spreads: Spreads,
+    /// Reading direction to record in each archive's ComicInfo.xml, and the
+    /// order split spreads come in (right to left unless set)
+    #[clap(long, value_enum)]
+    pub(crate) direction: Option<Direction>,
+    /// Also keep the pages as downloaded, in an archive of the same name under
+    /// `originals/` in the comic's directory
+    #[clap(long)]
+    pub(crate) keep_originals: bool,
+    /// Decode every page in full before accepting it, on top of the structure
+    /// checks every page gets; slower, but also catches damage inside the
+    /// picture data
+    #[clap(long)]
+    pub(crate) decode_pages: bool,
+    /// Leave out pages that look like one on the blocklist; see the `block`
+    /// command
+    #[clap(long)]
+    pub(crate) drop_blocked: bool,
+    /// Blocklist file [default: blocklist.txt in the config directory]
+    #[clap(long, value_name = "FILE")]
+    pub(crate) blocklist: Option<PathBuf>,
+    /// How many of the 64 bits of a page's hash may differ from a blocked
+    /// page's for it to still count as that page
+    #[clap(long, value_name = "BITS", default_value_t = DEFAULT_BLOCK_DISTANCE)]
+    pub(crate) block_distance: u32,
+    /// The blocklist itself, read by `load_blocklist`.
+    #[clap(skip)]
+    pub(crate) blocked: Blocklist,
+}
+
+impl Default for PageOptions {
+    fn default() -> Self {
+        PageOptions {
+            webp_to: None,
+            jpeg_quality: DEFAULT_JPEG_QUALITY,
+            device: None,
+            screen: None,
+            grayscale: false,
+            gamma: 1.0,
+            contrast: 0.0,
+            spreads: Spreads::Keep,
+            direction: None,
+            keep_originals: false,
+            decode_pages: false,
+            drop_blocked: false,
+            blocklist: None,
+            block_distance: DEFAULT_BLOCK_DISTANCE,
+            blocked: Blocklist::default(),
+        }
+    }
+}
+
+impl PageOptions {
+    /// The screen pages are fitted to, from `--device` or `--screen`.
+    fn fit(&self) -> Option<Screen> {
+        self.screen.or(self.device.map(Device::screen))
+    }
+
+    /// Whether every page is decoded and rewritten, not only WebP ones.
+    fn adjusts(&self) -> bool {
+        self.fit().is_some()
+            || self.grayscale
+            || self.gamma != 1.0
+            || self.contrast != 0.0
+            || self.spreads != Spreads::Keep
+    }
+
+    /// Rewrite the pages `names` in `dir`, returning the names to pack in
+    /// their place, in the same order. Names are relative to `dir` and may
+    /// lead into a subdirectory; the archive entry is the file name alone.
+    ///
+    /// Pages that match the blocklist are dropped first, and each drop is
+    /// reported on stderr: a page silently missing from a chapter is the kind
+    /// of thing a too-loose `--block-distance` does, and should be noticed.
+    pub(crate) fn apply(&self, dir: &Path, names: Vec<String>) -> Result<Vec<String>> {
+        if self.webp_to.is_none() && !self.adjusts() && self.blocked.is_empty() {
+            return Ok(names);
+        }
+        let mut packed = Vec::with_capacity(names.len());
+        for (i, name) in names.into_iter().enumerate() {
+            let bytes = fs::read(dir.join(&name))?;
+            // Pages the image crate cannot decode, AVIF, are packed as they
+            // came: neither compared nor rewritten.
+            if !PageFormat::sniff(&bytes).is_some_and(PageFormat::decodes) {
+                packed.push(name);
+                continue;
+            }
+            if !self.blocked.is_empty() {
+                let hash = page_hash(&image::load_from_memory(&bytes)?);
+                if let Some(entry) = self.blocked.matching(hash, self.block_distance) {
+                    log::info!("Dropped page {} ({}): matches blocked page {}", i + 1, name, entry);
+                    continue;
+                }
+            }
+            packed.extend(self.rewrite(dir, name, &bytes)?);
+        }
+        // A blocklist entry that matches everything — a blank page blocked by
+        // mistake, say — must not pack an empty chapter and call it done.
+        if packed.is_empty() {
+            return Err(AppError::ContentParsing(
+                "every page of the chapter matches the blocklist".to_string(),
+            ));
+        }
+        Ok(packed)
+    }
+
+    /// Read the blocklist into `blocked` if `--drop-blocked` asks for it.
+    pub(crate) fn load_blocklist(&mut self) -> Result<()> {
+        if self.drop_blocked {
+            self.blocked = Blocklist::load(&blocklist_path(self.blocklist.as_deref())?)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite page `name`, whose contents are `bytes`, if anything asks for
+    /// it, into one page or — for a split spread — two. Whether a page is WebP
+    /// is read from its bytes, not its name: pages saved by older versions
+    /// kept the server's names, and the tunnels serve WebP under `.jpg` too.
+    fn rewrite(&self, dir: &Path, name: String, bytes: &[u8]) -> Result<Vec<String>> {
+        if !self.adjusts() && !is_webp(bytes) {
+            return Ok(vec![name]);
+        }
+        let target = self.webp_to.unwrap_or(WebpTarget::Jpeg);
+        let ext = target.extension();
+        let stem = format!("{PROCESSED_DIR}/{}", image_stem(&name));
+        // Whether a page is a spread is only known once it is decoded, so a
+        // finished page is looked for under both of the names it may have.
+        let whole = vec![format!("{stem}.{ext}")];
+        let halves = vec![format!("{stem}_1.{ext}"), format!("{stem}_2.{ext}")];
+        for done in [&whole, &halves] {
+            if done.iter().all(|n| dir.join(n).exists()) {
+                return Ok(done.clone());
+            }
+        }
+        fs::create_dir_all(dir.join(PROCESSED_DIR))?;
+        let parts = self.lay_out(image::load_from_memory(bytes)?);
+        let new_names = if parts.len() == 2 { halves } else { whole };
+        for (part, new_name) in parts.into_iter().zip(&new_names) {
+            self.write_page(self.adjust(part), &dir.join(new_name), target)?;
+        }
+        Ok(new_names)
+    }
+
+    /// Split or rotate `image` if it is a spread and `--spreads` says to; the
+    /// pages come back in reading order.
+    pub(crate) fn lay_out(&self, image: DynamicImage) -> Vec<DynamicImage> {
+        if image.width() <= image.height() {
+            return vec![image];
+        }
+        let rtl = self.direction != Some(Direction::Ltr);
+        match self.spreads {
+            Spreads::Keep => vec![image],
+            Spreads::Split => {
+                let (width, height) = (image.width(), image.height());
+                let left = image.crop_imm(0, 0, width / 2, height);
+                let right = image.crop_imm(width / 2, 0, width - width / 2, height);
+                if rtl { vec![right, left] } else { vec![left, right] }
+            }
+            // The page read first ends up on top.
+            Spreads::Rotate if rtl => vec![image.rotate270()],
+            Spreads::Rotate => vec![image.rotate90()],
+        }
+    }
+
+    fn write_page(&self, image: DynamicImage, dst: &Path, target: WebpTarget) -> Result<()> {
+        write_atomic(dst, |file| {
+            let mut out = io::BufWriter::new(file);
+            match target {
+                // JPEG has no alpha channel; comic pages are opaque anyway.
+                WebpTarget::Jpeg => {
+                    let image = match image {
+                        DynamicImage::ImageLuma8(_) => image,
+                        _ => DynamicImage::ImageRgb8(image.to_rgb8()),
+                    };
+                    image.write_with_encoder(JpegEncoder::new_with_quality(&mut out, self.jpeg_quality))?
+                }
+                WebpTarget::Png => image.write_with_encoder(PngEncoder::new(&mut out))?,
+            }
+            out.flush()?;
+            Ok(())
+        })
+    }
+
+    /// Fit, desaturate and tone one page, cheapest order first: everything
+    /// after the resize runs on the smaller image.
+    pub(crate) fn adjust(&self, mut image: DynamicImage) -> DynamicImage {
+        if let Some(Screen { width, height }) = self.fit()
+            && (image.width() > width || image.height() > height)
+        {
+            // Keeps the aspect ratio; pages smaller than the screen are left
+            // alone rather than blown up.
+            image = image.resize(width, height, FilterType::Lanczos3);
+        }
+        if self.grayscale {
+            image = DynamicImage::ImageLuma8(image.to_luma8());
+        }
+        if self.contrast != 0.0 {
+            image = image.adjust_contrast(self.contrast);
+        }
+        if self.gamma != 1.0 {
+            let lut: Vec<u8> = (0..=255u8)
+                .map(|v| (255.0 * (v as f32 / 255.0).powf(self.gamma)).round() as u8)
+                .collect();
+            image = match image {
+                DynamicImage::ImageLuma8(mut gray) => {
+                    gray.pixels_mut().for_each(|p| p.0[0] = lut[p.0[0] as usize]);
+                    DynamicImage::ImageLuma8(gray)
+                }
+                other => {
+                    let mut rgb = other.to_rgb8();
+                    rgb.pixels_mut()
+                        .for_each(|p| p.0.iter_mut().for_each(|c| *c = lut[*c as usize]));
+                    DynamicImage::ImageRgb8(rgb)
+                }
+            };
+        }
+        image
+    }
+}
+
+/// `name` without its image extensions — both of them, for `001.jpg.webp`.
+pub(crate) fn image_stem(name: &str) -> &str {
+    let mut stem = name;
+    for _ in 0..2 {
+        match stem.rsplit_once('.') {
+            Some((rest, ext)) if IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()) => {
+                stem = rest
+            }
+            _ => break,
+        }
+    }
+    stem
+}
+
+/// Name of the metadata entry comic readers look for in an archive.
+pub(crate) const COMIC_INFO: &str = "ComicInfo.xml";
+
+/// A ComicInfo.xml naming the series and chapter and, through `Manga`, the
+/// reading direction: `YesAndRightToLeft` is what makes readers such as
+/// Komga, Kavita and KOReader open a book from the right.
+pub(crate) fn comic_info(series: &str, title: &str, direction: Direction) -> String {
+    let manga = match direction {
+        Direction::Rtl => "YesAndRightToLeft",
+        Direction::Ltr => "No",
+    };
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <ComicInfo xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
+         xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\">\n\
+         \x20 <Series>{}</Series>\n\
+         \x20 <Title>{}</Title>\n\
+         \x20 <Manga>{manga}</Manga>\n\
+         </ComicInfo>\n",
+        xml_escape(series),
+        xml_escape(title)
+    )
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Hamming distance within which a page matches a blocked one by default. Out
+/// of 64 bits, this absorbs re-encoding and small resizes but not a different
+/// page.
+const DEFAULT_BLOCK_DISTANCE: u32 = 6;
+
+/// A difference hash of `image`: shrunk to 9x8 in grayscale, each bit says
+/// whether a pixel is brighter than its right neighbour. The same credit page
+/// re-encoded, resized or served as WebP instead of JPEG hashes to the same or
+/// nearly the same bits, which a byte-for-byte checksum would not.
+pub(crate) fn page_hash(image: &DynamicImage) -> u64 {
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x, y).0[0] > small.get_pixel(x + 1, y).0[0];
+            hash = hash << 1 | brighter as u64;
+        }
+    }
+    hash
+}
+
+/// `--blocklist`, or `blocklist.txt` in the config directory.
+pub(crate) fn blocklist_path(given: Option<&Path>) -> Result<PathBuf> {
+    match given {
+        Some(path) => Ok(path.to_path_buf()),
+        None => config_dir().map(|dir| dir.join("blocklist.txt")).ok_or_else(|| {
+            AppError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no config directory to keep the blocklist in; pass --blocklist FILE",
+            ))
+        }),
+    }
+}
+
+/// Pages to drop, by hash. On disk, one page per line: the hash as 16 hex
+/// digits, then optionally a note saying what it is. Lines starting with `#`
+/// are comments, so the file can be kept by hand as well as by `block`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Blocklist {
+    entries: Vec<BlockEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct BlockEntry {
+    pub(crate) hash: u64,
+    pub(crate) note: String,
+}
+
+impl std::fmt::Display for BlockEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.hash)?;
+        if !self.note.is_empty() {
+            write!(f, " ({})", self.note)?;
+        }
+        Ok(())
+    }
+}
+
+impl Blocklist {
+    /// Read the blocklist at `path`; a file that does not exist yet is an
+    /// empty list.
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Blocklist::default()),
+            Err(e) => return Err(e.into()),
+        };
+        Self::parse(&text).map_err(|(n, line)| {
+            AppError::ContentParsing(format!("{} line {}: not a page hash: {}", path.display(), n, line))
+        })
+    }
+
+    /// Parse the blocklist text, or say which line is not an entry.
+    pub(crate) fn parse(text: &str) -> std::result::Result<Self, (usize, String)> {
+        let mut entries = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (hex, note) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let hash = u64::from_str_radix(hex, 16).map_err(|_| (n + 1, line.to_string()))?;
+            entries.push(BlockEntry { hash, note: note.trim().to_string() });
+        }
+        Ok(Blocklist { entries })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The entry `hash` is within `distance` bits of, nearest first.
+    pub(crate) fn matching(&self, hash: u64, distance: u32) -> Option<&BlockEntry> {
+        self.entries
+            .iter()
+            .map(|entry| ((entry.hash ^ hash).count_ones(), entry))
+            .filter(|&(d, _)| d <= distance)
+            .min_by_key(|&(d, _)| d)
+            .map(|(_, entry)| entry)
+    }
+
+    /// Append an entry to the blocklist file at `path`, creating it and its
+    /// directory as needed.
+    pub(crate) fn append(path: &Path, entry: &BlockEntry) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{:016x} {}", entry.hash, entry.note.replace('\n', " "))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+//! How a download shows its progress: bars on the terminal for a person, or
+//! with `--progress json` one JSON object per line on stdout, for a program
+//! wrapping this one.
+
+use crate::summary::Failed;
+use serde::Serialize;
+use std::{io::Write, path::Path};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub(crate) enum Progress {
+    /// Progress bars on stderr
+    #[default]
+    Bars,
+    /// One JSON event per line on stdout, in place of the bars
+    Json,
+}
+
+impl Progress {
+    pub(crate) fn is_json(self) -> bool {
+        self == Progress::Json
+    }
+
+    /// Print `event`, if events are what is shown.
+    pub(crate) fn emit(self, event: Event) {
+        if !self.is_json() {
+            return;
+        }
+        let mut line = serde_json::to_vec(&event).expect("events serialize");
+        line.push(b'\n');
+        // Whoever reads the events going away is no reason to stop the
+        // download; the files it writes are the point of it.
+        let mut stdout = std::io::stdout().lock();
+        let _ = stdout.write_all(&line).and_then(|_| stdout.flush());
+    }
+}
+
+/// Something that happened in a download. Chapters are numbered from 1, as
+/// the chapter prompt and `--chapters` number them, and so are pages.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum Event<'a> {
+    ComicLoaded { id: usize, title: &'a str, chapters: usize },
+    ChapterStarted { chapter: usize, name: &'a str, pages: usize },
+    PageDownloaded { chapter: usize, index: usize, total: usize, bytes: usize },
+    ChapterPackaged { chapter: usize, name: &'a str, path: &'a Path },
+    /// The chapter's archive was there already.
+    ChapterSkipped { chapter: usize, name: &'a str, path: &'a Path },
+    ChapterFailed { chapter: usize, name: &'a str, error: String },
+    /// The run is over; see `Summary`.
+    Summary { downloaded: usize, skipped: usize, failed: &'a [Failed], pages: usize, bytes: u64, seconds: f64 },
+}
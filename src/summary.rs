@@ -0,0 +1,114 @@
+//! The account of a download given once it ends, and whether the run counts
+//! as failed for its exit status.
+
+use crate::{progress::Event, AppError, Result};
+use indicatif::{FormattedDuration, HumanBytes};
+use serde::Serialize;
+use std::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Instant,
+};
+
+/// When a run with failed chapters exits with an error.
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub(crate) enum FailOn {
+    /// When any chapter failed
+    #[default]
+    Any,
+    /// Only when every chapter failed
+    All,
+}
+
+/// The pages a comic's downloads fetched and how large they were. Pages
+/// already on disk are not counted: nothing was fetched for them.
+#[derive(Default)]
+pub(crate) struct Tally {
+    pages: AtomicUsize,
+    bytes: AtomicU64,
+}
+
+impl Tally {
+    pub(crate) fn page(&self, bytes: usize) {
+        self.pages.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Failed {
+    /// Numbered from 1, as the chapter prompt numbers them.
+    pub(crate) chapter: usize,
+    pub(crate) name: String,
+}
+
+/// How each chapter of a run went.
+pub(crate) struct Summary {
+    started: Instant,
+    pub(crate) downloaded: usize,
+    pub(crate) skipped: usize,
+    pub(crate) failed: Vec<Failed>,
+}
+
+impl Summary {
+    pub(crate) fn new() -> Self {
+        Summary { started: Instant::now(), downloaded: 0, skipped: 0, failed: Vec::new() }
+    }
+
+    /// Count chapter `index`, named `name`, as `result` says it went.
+    pub(crate) fn record(&mut self, index: usize, name: &str, result: &Result<bool>) {
+        match result {
+            Ok(true) => self.downloaded += 1,
+            Ok(false) => self.skipped += 1,
+            Err(_) => self.failed.push(Failed { chapter: index + 1, name: name.to_string() }),
+        }
+    }
+
+    fn chapters(&self) -> usize {
+        self.downloaded + self.skipped + self.failed.len()
+    }
+
+    /// The summary as `Event::Summary`, for `--progress json`.
+    pub(crate) fn event<'a>(&'a self, tally: &Tally) -> Event<'a> {
+        Event::Summary {
+            downloaded: self.downloaded,
+            skipped: self.skipped,
+            failed: &self.failed,
+            pages: tally.pages.load(Ordering::Relaxed),
+            bytes: tally.bytes.load(Ordering::Relaxed),
+            seconds: self.started.elapsed().as_secs_f64(),
+        }
+    }
+
+    /// The summary for a person: the counts on one line, then the chapters
+    /// that failed, if any did. Nothing for a run that had no chapters to
+    /// download.
+    pub(crate) fn lines(&self, tally: &Tally) -> Vec<String> {
+        if self.chapters() == 0 {
+            return Vec::new();
+        }
+        let mut lines = vec![format!(
+            "Finished in {}: {} downloaded, {} skipped, {} failed; {} page(s), {}",
+            FormattedDuration(self.started.elapsed()),
+            self.downloaded,
+            self.skipped,
+            self.failed.len(),
+            tally.pages.load(Ordering::Relaxed),
+            HumanBytes(tally.bytes.load(Ordering::Relaxed)),
+        )];
+        lines.extend(self.failed.iter().map(|f| format!("Failed chapter {}: {}", f.chapter, f.name)));
+        lines
+    }
+
+    /// An error if the run failed by `fail_on`'s measure.
+    pub(crate) fn verdict(&self, fail_on: FailOn) -> Result<()> {
+        let failed = self.failed.len();
+        let counts = match fail_on {
+            FailOn::Any => failed > 0,
+            FailOn::All => failed > 0 && failed == self.chapters(),
+        };
+        if counts {
+            return Err(AppError::ChaptersFailed(failed, self.chapters()));
+        }
+        Ok(())
+    }
+}
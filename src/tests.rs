@@ -13,11 +13,23 @@ fn load_test_html(filename: &str) -> String {
 /// every call site only invites the copies to drift apart.
 fn test_comic(tunnel: &str, book_dir: &Path) -> Comic {
     Comic {
-        client: reqwest::blocking::Client::new(),
+        http: Http {
+            runtime: runtime().unwrap(),
+            transport: Box::new(Client::new()),
+            throttle: Throttle::default(),
+            cookies: CookieJar::default(),
+            cache: HtmlCache::default(),
+        },
+        id: 1,
         tunnel: Url::parse(tunnel).expect("test tunnel must be a valid absolute URL"),
-        delay: Duration::from_millis(0),
+        pages: PageOptions::default(),
         title: "Test Comic".to_string(),
+        metadata: ComicMetadata::default(),
         chapters: vec![],
+        chapter_source: ChapterSource::List,
+        progress: Progress::Bars,
+        fail_on: FailOn::Any,
+        tally: Tally::default(),
         book_safe: "Test Comic".to_string(),
         book_dir: book_dir.to_path_buf(),
     }
@@ -237,7 +249,7 @@ fn test_unpack_packed_empty_files_is_error() {
 #[test]
 fn test_comic_metadata_extraction_from_real_html() {
     let html = load_test_html("comic_40811.html");
-    let (title, chapters) = Comic::parse_comic_html(&html).expect("Failed to parse comic HTML");
+    let (title, chapters, _, _) = Comic::parse_comic_html(&html).expect("Failed to parse comic HTML");
 
     // Verify title
     assert_eq!(title, "FX戰士久留美");
@@ -261,10 +273,11 @@ fn test_comic_metadata_extraction_from_real_html() {
 #[test]
 fn test_comic_metadata_extraction_adult_gated() {
     let html = load_test_html("comic_10528.html");
-    let (title, chapters) = Comic::parse_comic_html(&html)
+    let (title, chapters, _, source) = Comic::parse_comic_html(&html)
         .expect("Failed to parse adult-gated comic HTML");
 
     assert_eq!(title, "GATE奇幻自衛隊");
+    assert_eq!(source, ChapterSource::ViewState);
     assert!(!chapters.is_empty(), "Should find chapters via __VIEWSTATE fallback");
     for chapter in &chapters {
         assert!(!chapter.name.is_empty());
@@ -290,7 +303,48 @@ fn test_comic_metadata_extraction_no_chapters_is_error() {
             <p>本作品暫不提供觀看</p>
         </body></html>
     "#;
-    assert!(Comic::parse_comic_html(html).is_err());
+    assert!(matches!(Comic::parse_comic_html(html), Err(AppError::ContentParsing(_))));
+}
+
+#[test]
+fn test_adult_gate_is_detected() {
+    // The gate's warning without a view state to fall back on: the error
+    // names the gate and the option, rather than blaming the page layout.
+    let comic = r#"
+        <html><body>
+            <div class="book-title"><h1>某漫畫</h1></div>
+            <div class="warning-bar"><p>如果你法定年齡已超過18歲，
+                <a href="javascript:;" id="checkAdult">請點擊此處繼續閱讀！</a></p></div>
+        </body></html>
+    "#;
+    match Comic::parse_comic_html(comic) {
+        Err(AppError::AdultGated(title)) => assert_eq!(title, "某漫畫"),
+        other => panic!("expected AdultGated, got {:?}", other.map(|(title, ..)| title)),
+    }
+    assert!(matches!(Comic::parse_chapter_html(comic), Err(AppError::AdultGated(_))));
+
+    // The visible list is reported as such.
+    let listed = r#"
+        <html><body>
+            <div class="book-title"><h1>某漫畫</h1></div>
+            <div class="chapter-list"><ul><li><a href="/comic/1/2.html" title="第01話">第01話</a></li></ul></div>
+        </body></html>
+    "#;
+    let (_, chapters, _, source) = Comic::parse_comic_html(listed).unwrap();
+    assert_eq!(chapters.len(), 1);
+    assert_eq!(source, ChapterSource::List);
+
+    // Once confirmed, a page gated all the same is not met with the same advice.
+    let mut http = Http {
+        runtime: runtime().unwrap(),
+        transport: Box::new(Client::new()),
+        throttle: Throttle::default(),
+        cookies: CookieJar::default(),
+        cache: HtmlCache::default(),
+    };
+    assert!(matches!(http.gate(Comic::parse_chapter_html(comic)), Err(AppError::AdultGated(_))));
+    http.cookies.accept_adult = true;
+    assert!(matches!(http.gate(Comic::parse_chapter_html(comic)), Err(AppError::ContentParsing(_))));
 }
 
 #[test]
@@ -308,13 +362,84 @@ fn test_comic_title_is_trimmed_and_must_not_be_blank() {
             </ul></div>
         </body></html>
     "#;
-    let (title, _) = Comic::parse_comic_html(html).expect("Failed to parse comic HTML");
+    let (title, _, _, _) = Comic::parse_comic_html(html).expect("Failed to parse comic HTML");
     assert_eq!(title, "某漫畫");
 
     let blank = html.replace("某漫畫", " ");
     assert!(Comic::parse_comic_html(&blank).is_err());
 }
 
+#[test]
+fn test_comic_metadata_is_read_by_label() {
+    // The detail block as the site lays it out, minus the 出品年代 line: fields
+    // are matched by their label, so a missing line must not shift the others
+    // into the wrong slots.
+    let html = r#"
+        <html><body>
+            <div class="book-title"><h1>某漫畫</h1><h2>あるマンガ</h2></div>
+            <ul class="detail-list cf">
+                <li><span><strong>漫畫地區：</strong><a href="/list/japan/">日本</a></span>
+                    <span><strong>字母索引：</strong><a href="/list/letter/m/">M</a></span></li>
+                <li><span><strong>漫畫劇情：</strong><a href="/list/rexue/">熱血</a>
+                    <a href="/list/maoxian/">冒險</a></span>
+                    <span><strong>漫畫作者：</strong><a href="/author/1/">某作者</a></span></li>
+                <li><span><strong>漫畫別名：</strong>別名甲</span></li>
+                <li class="status"><span><strong>漫畫狀態：</strong><span class="red">連載中</span>。
+                    最近於 [<span class="red">2024-01-02</span>] 更新至
+                    [ <a href="/comic/1/102.html" class="blue">第02話</a> ]。</span></li>
+            </ul>
+            <div id="intro-all"><p> 一段簡介。 </p></div>
+            <div class="chapter-list"><ul>
+                <li><a href="/comic/1/101.html" title="第01話">第01話</a></li>
+            </ul></div>
+        </body></html>
+    "#;
+    let (_, _, meta, _) = Comic::parse_comic_html(html).expect("Failed to parse comic HTML");
+
+    assert_eq!(meta.subtitle.as_deref(), Some("あるマンガ"));
+    assert_eq!(meta.year, None);
+    assert_eq!(meta.region.as_deref(), Some("日本"));
+    assert_eq!(meta.genres, vec!["熱血", "冒險"]);
+    assert_eq!(meta.authors, vec!["某作者"]);
+    // A value written as plain text rather than as links.
+    assert_eq!(meta.aliases, vec!["別名甲"]);
+    // The status line's nested spans belong to the status, not to fields of
+    // their own.
+    assert_eq!(meta.status.as_deref(), Some("連載中"));
+    assert_eq!(meta.last_update.as_deref(), Some("2024-01-02"));
+    assert_eq!(meta.latest_chapter.as_deref(), Some("第02話"));
+    assert_eq!(meta.description.as_deref(), Some("一段簡介。"));
+}
+
+#[test]
+fn test_info_document_schema() {
+    // Scripts depend on these field names; renaming one is a schema bump.
+    let temp_dir = TempDir::new().unwrap();
+    let mut comic = test_comic("https://i.hamreus.com", temp_dir.path());
+    comic.chapters = vec![
+        Chapter { name: "第01話".to_string(), href: "/comic/1/101.html".to_string(), group: "單話".to_string() },
+        Chapter { name: "第02話".to_string(), href: "/comic/1/102.html".to_string(), group: "單話".to_string() },
+    ];
+    std::fs::write(comic.zip_path(1), b"done").unwrap();
+
+    let doc = serde_json::to_value(comic.info()).unwrap();
+    assert_eq!(doc["schema_version"], INFO_SCHEMA_VERSION);
+    assert_eq!(doc["comic_id"], 1);
+    assert_eq!(doc["title"], "Test Comic");
+    assert!(doc["metadata"]["genres"].is_array());
+    assert_eq!(
+        doc["chapters"][0],
+        serde_json::json!({
+            "index": 1,
+            "name": "第01話",
+            "href": "/comic/1/101.html",
+            "group": "單話",
+            "downloaded": false,
+        })
+    );
+    assert_eq!(doc["chapters"][1]["downloaded"], true);
+}
+
 #[test]
 fn test_extract_chapters_group_from_nearest_h4() {
     // The group must come from the nearest preceding sibling h4, skipping
@@ -416,6 +541,25 @@ fn test_chapter_parsing_from_real_html() {
 }
 
 
+/// Chapters with the given names and group headings; the hrefs are never
+/// fetched.
+fn chapters_named(names: &[(&str, &str)]) -> Vec<Chapter> {
+    names
+        .iter()
+        .enumerate()
+        .map(|(i, (group, name))| Chapter {
+            name: name.to_string(),
+            href: format!("/comic/1/{i}.html"),
+            group: group.to_string(),
+        })
+        .collect()
+}
+
+fn numbered_chapters(count: usize) -> Vec<Chapter> {
+    let names: Vec<String> = (1..=count).map(|i| format!("第{i:02}話")).collect();
+    chapters_named(&names.iter().map(|n| ("單話", n.as_str())).collect::<Vec<_>>())
+}
+
 #[test]
 fn test_prompt_for_chapters_retry_on_invalid() {
     // The prompt loop re-prompts on any input rejected by
@@ -424,8 +568,7 @@ fn test_prompt_for_chapters_retry_on_invalid() {
     // through unchanged.
     // First input is out of bounds (11 > 10), second is invalid format, third is valid.
     let mut input = std::io::Cursor::new("11\ninvalid\n1-3,5\n");
-    let chapters_count = 10;
-    let result: Vec<usize> = prompt_for_chapters(&mut input, chapters_count).unwrap();
+    let result: Vec<usize> = prompt_for_chapters(&mut input, &numbered_chapters(10)).unwrap();
 
     assert_eq!(result, vec![0, 1, 2, 4]);
 }
@@ -458,14 +601,14 @@ fn test_parse_chapter_selection() {
 fn test_prompt_for_chapters_eof() {
     // stdin closed immediately: must error out instead of looping forever
     let mut input = std::io::Cursor::new("");
-    assert!(prompt_for_chapters(&mut input, 10).is_err());
+    assert!(prompt_for_chapters(&mut input, &numbered_chapters(10)).is_err());
 }
 
 #[test]
 fn test_prompt_for_chapters_eof_after_invalid_input() {
     // Invalid input followed by EOF: must error out after the retry
     let mut input = std::io::Cursor::new("999\n");
-    assert!(prompt_for_chapters(&mut input, 10).is_err());
+    assert!(prompt_for_chapters(&mut input, &numbered_chapters(10)).is_err());
 }
 
 #[test]
@@ -828,9 +971,20 @@ fn test_image_url_anchors_the_page_path_on_the_tunnel() {
 
 #[test]
 fn test_looks_like_image_accepts_the_served_formats() {
-    // Enough of a header for each of the two signatures the allow list carries.
-    assert!(looks_like_image(b"\xFF\xD8\xFF\xE0\x00\x10JFIF"));
-    assert!(looks_like_image(b"RIFF\x24\x00\x00\x00WEBP"));
+    // Enough of a header for each of the signatures the allow list carries.
+    let heads: [(&[u8], PageFormat); 7] = [
+        (b"\xFF\xD8\xFF\xE0\x00\x10JFIF", PageFormat::Jpeg),
+        (b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0d", PageFormat::Png),
+        (b"GIF89a\x01\x00\x01\x00\x00\x00", PageFormat::Gif),
+        (b"GIF87a\x01\x00\x01\x00\x00\x00", PageFormat::Gif),
+        (b"RIFF\x24\x00\x00\x00WEBP", PageFormat::WebP),
+        (b"\x00\x00\x00\x20ftypavif", PageFormat::Avif),
+        (b"\x00\x00\x00\x20ftypavis", PageFormat::Avif),
+    ];
+    for (head, format) in heads {
+        assert!(looks_like_image(head));
+        assert_eq!(PageFormat::sniff(head), Some(format));
+    }
 }
 
 #[test]
@@ -845,12 +999,12 @@ fn test_looks_like_image_rejects_non_images() {
     assert!(!looks_like_image(b"RIFF\x24\x00"));
     // Nothing at all, which is what an empty 200 body reads as.
     assert!(!looks_like_image(b""));
-    // Image formats outside the two the tunnel serves are turned away as well.
-    // The list is deliberately narrow: picking one up has to be a deliberate
-    // edit, not something that slips in behind a generic "is an image" test.
-    assert!(!looks_like_image(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0d"));
-    assert!(!looks_like_image(b"GIF89a\x01\x00\x01\x00\x00\x00"));
-    assert!(!looks_like_image(b"\x00\x00\x00\x20ftypavif"));
+    // Formats outside the allow list are turned away as well, image or not:
+    // picking one up has to be a deliberate edit, not something that slips in
+    // behind a generic "is an image" test.
+    assert!(!looks_like_image(b"BM\x36\x00\x0c\x00\x00\x00\x00\x00\x36\x00"));
+    assert!(!looks_like_image(b"\x00\x00\x00\x18ftypheic"));
+    assert!(!looks_like_image(b"\x00\x00\x00\x18ftypmp42"));
 }
 
 #[test]
@@ -889,7 +1043,8 @@ fn test_download_rejects_non_image_body() {
     let chap = test_chapter(&["test.jpg"]);
 
     let err = comic
-        .download_images(&chap, &chapter_dir, &bar, "http://localhost/chapter")
+        .http
+        .block_on(comic.download_images(0, &chap, &chapter_dir, &bar, "http://localhost/chapter"))
         .expect_err("an HTML body must not be accepted as a page");
     assert!(
         err.to_string().contains("is not an image"),
@@ -914,8 +1069,10 @@ fn test_download_incomplete_file() {
     let listener = TcpListener::bind("127.0.0.1:0").unwrap();
     let port = listener.local_addr().unwrap().port();
 
+    // A page cut short is fetched again, so every attempt gets the same
+    // truncated answer.
     let server_thread = thread::spawn(move || {
-        if let Ok((mut stream, _)) = listener.accept() {
+        for mut stream in listener.incoming().take(PAGE_ATTEMPTS).flatten() {
             let mut buffer = [0; 512];
             let _ = stream.read(&mut buffer); // Read request
 
@@ -941,7 +1098,7 @@ fn test_download_incomplete_file() {
     let chap = test_chapter(&["test.jpg"]);
 
     // 3. Execute download and verify result
-    let result = comic.download_images(&chap, &chapter_dir, &bar, "http://localhost/chapter");
+    let result = comic.http.block_on(comic.download_images(0, &chap, &chapter_dir, &bar, "http://localhost/chapter"));
 
     assert!(result.is_err());
     let err_msg = format!("{}", result.unwrap_err());
@@ -981,7 +1138,7 @@ fn test_download_resume_logic() {
 
     // If the logic is correct, it will see 0_test.jpg exists and skip network calls.
     // If it attempts to download, it will fail because the tunnel host is invalid.
-    let result = comic.download_images(&chap, &chapter_dir, &bar, "http://localhost/chapter");
+    let result = comic.http.block_on(comic.download_images(0, &chap, &chapter_dir, &bar, "http://localhost/chapter"));
 
     assert!(result.is_ok(), "Should skip existing file and return Ok, but got error");
     // Skipped pages still have to be reported, or compress_chapter would omit them.
@@ -1096,7 +1253,7 @@ fn test_multiple_ul_chapter_ordering() {
     // Reversing per <ul> while keeping the <ul> order must therefore produce
     // one continuous ascending run across the pager boundaries.
     let html = load_test_html("comic_1128.html");
-    let (title, chapters) = Comic::parse_comic_html(&html).expect("Failed to parse comic HTML");
+    let (title, chapters, _, _) = Comic::parse_comic_html(&html).expect("Failed to parse comic HTML");
 
     assert_eq!(title, "ONE PIECE航海王");
 
@@ -1133,3 +1290,1215 @@ fn test_multiple_ul_chapter_ordering() {
         "pager links leaked into the chapter list"
     );
 }
+
+#[test]
+fn test_bare_url_is_shorthand_for_download() {
+    // Existing scripts call the program with just a URL and download options;
+    // that must keep parsing, and mean the same as the `download` subcommand.
+    let args = Args::try_parse_from(["mhg_dl_rs", "12345", "-t", "1", "-o", "out"]).unwrap();
+    assert!(args.command.is_none());
+    assert_eq!(args.url.as_deref(), Some("12345"));
+    assert_eq!(args.download.tunnel, 1);
+    assert_eq!(args.download.output_dir, PathBuf::from("out"));
+
+    let args = Args::try_parse_from(["mhg_dl_rs", "download", "12345", "-c", "1-3"]).unwrap();
+    match args.command {
+        Some(Command::Download { url, select, .. }) => {
+            assert_eq!(url, "12345");
+            assert_eq!(select.chapters.as_deref(), Some("1-3"));
+        }
+        _ => panic!("expected the download subcommand"),
+    }
+
+    // So does the old search option, meaning the `search` subcommand.
+    let args = Args::try_parse_from(["mhg_dl_rs", "-s", "金田一", "-o", "out"]).unwrap();
+    assert!(args.command.is_none() && args.url.is_none());
+    assert_eq!(args.search.as_deref(), Some("金田一"));
+    assert_eq!(args.download.output_dir, PathBuf::from("out"));
+    assert!(Args::try_parse_from(["mhg_dl_rs", "--search", "x", "12345"]).is_err());
+
+    // Neither a URL nor a subcommand is an error, not an empty download.
+    assert!(Args::try_parse_from(["mhg_dl_rs"]).is_err());
+}
+
+#[test]
+fn test_verify_archive() {
+    use zip::write::FileOptions;
+
+    let temp_dir = TempDir::new().unwrap();
+    let write_zip = |name: &str, pages: &[&[u8]]| {
+        let path = temp_dir.path().join(name);
+        let mut zip = ZipWriter::new(std::fs::File::create(&path).unwrap());
+        for (i, page) in pages.iter().enumerate() {
+            zip.start_file(format!("{i}.jpg"), FileOptions::default()).unwrap();
+            zip.write_all(page).unwrap();
+        }
+        zip.finish().unwrap();
+        path
+    };
+
+    let jpeg = encoded(&test_page(2, 64, 64), image::ImageFormat::Jpeg);
+    let good = write_zip("good.cbz", &[&jpeg]);
+    assert!(verify_archive(&good).is_ok());
+
+    // A page that is not an image, as an anti-hotlink page sealed by an older
+    // version would be.
+    let html = write_zip("html.cbz", &[&jpeg, b"<!DOCTYPE html>"]);
+    let err = verify_archive(&html).unwrap_err().to_string();
+    assert!(err.contains("1.jpg is not an image"), "Error message was: {}", err);
+
+    // A page cut short in transit, sealed before pages were checked in full.
+    let short = write_zip("short.cbz", &[&jpeg[..jpeg.len() / 2]]);
+    let err = verify_archive(&short).unwrap_err().to_string();
+    assert!(err.contains("0.jpg: JPEG ends before its EOI marker"), "Error message was: {}", err);
+
+    // An archive cut short on disk does not open at all.
+    let bytes = std::fs::read(&good).unwrap();
+    let cut = temp_dir.path().join("cut.cbz");
+    std::fs::write(&cut, &bytes[..bytes.len() / 2]).unwrap();
+    assert!(verify_archive(&cut).is_err());
+
+    assert!(verify_archive(&write_zip("empty.cbz", &[])).is_err());
+}
+
+#[test]
+fn test_search_selection_flags() {
+    let parse = |extra: &[&str]| {
+        let mut argv = vec!["mhg_dl_rs", "search", "金田一"];
+        argv.extend_from_slice(extra);
+        Args::try_parse_from(argv)
+    };
+
+    match parse(&["--first", "-c", "1-3"]).unwrap().command {
+        Some(Command::Search { pick, .. }) => {
+            assert!(pick.first);
+            assert_eq!(pick.pick, None);
+            assert_eq!(pick.max_pages, 5);
+            assert_eq!(pick.select.chapters.as_deref(), Some("1-3"));
+        }
+        _ => panic!("expected the search subcommand"),
+    }
+
+    // Results are numbered from 1, and a page budget of zero fetches nothing.
+    assert!(parse(&["--pick", "0"]).is_err());
+    assert!(parse(&["--max-pages", "0"]).is_err());
+    // --json only lists; it cannot also pick something to download.
+    assert!(parse(&["--json", "--pick", "2"]).is_err());
+    assert!(parse(&["--json", "--first"]).is_err());
+    assert!(parse(&["--json", "-c", "1"]).is_err());
+    assert!(parse(&["--first", "--pick", "2"]).is_err());
+}
+
+#[test]
+fn test_parse_listing_shapes() {
+    // The three browse listings lay entries out differently; each must come
+    // out as the same title and ID, taken from the entry's first comic link.
+    let category = r#"
+        <ul id="contList">
+            <li><a class="bcover" href="/comic/100/" title="甲漫畫"><img></a>
+                <p class="ell"><a href="/comic/100/" title="甲漫畫">甲漫畫</a></p></li>
+            <li><a class="bcover" href="/comic/200/" title="乙漫畫"><img></a></li>
+        </ul>
+        <div class="pager-cont"><div class="pager">
+            <a href="/list/update_p2.html">下一頁</a>
+        </div></div>
+    "#;
+    let (results, next) = parse_listing(category);
+    let got: Vec<(&str, usize)> = results.iter().map(|r| (r.title.as_str(), r.comic_id)).collect();
+    assert_eq!(got, vec![("甲漫畫", 100), ("乙漫畫", 200)]);
+    assert_eq!(next.as_deref(), Some("/list/update_p2.html"));
+
+    // The ranking table names the comic in the link text, and links the
+    // latest chapter after it.
+    let rank = r#"
+        <div class="top-cont"><table>
+            <tr><td class="rank-no">1</td>
+                <td class="rank-title"><h5><a href="/comic/300/">丙漫畫</a></h5></td>
+                <td class="rank-update"><a href="/comic/300/9.html">第9話</a></td></tr>
+        </table></div>
+    "#;
+    let (results, next) = parse_listing(rank);
+    assert_eq!(results.len(), 1);
+    assert_eq!((results[0].title.as_str(), results[0].comic_id), ("丙漫畫", 300));
+    assert!(next.is_none());
+
+    // The update page lists a comic once for every day it was updated on.
+    let updates = r#"
+        <div class="latest-list"><ul>
+            <li><a class="cover" href="/comic/400/" title="丁漫畫"></a></li>
+            <li><a class="cover" href="/comic/500/" title="戊漫畫"></a></li>
+        </ul><ul>
+            <li><a class="cover" href="/comic/400/" title="丁漫畫"></a></li>
+        </ul></div>
+    "#;
+    let (results, _) = parse_listing(updates);
+    let ids: Vec<usize> = results.iter().map(|r| r.comic_id).collect();
+    assert_eq!(ids, vec![400, 500]);
+}
+
+#[test]
+fn test_browse_listing_paths() {
+    assert_eq!(category_path(None, None, None, None, ListSort::Index), "/list/");
+    assert_eq!(category_path(None, None, None, None, ListSort::View), "/list/view.html");
+    assert_eq!(
+        category_path(
+            Some(Region::Japan),
+            Some("rexue"),
+            Some(Audience::Shaonian),
+            Some(SeriesStatus::Wanjie),
+            ListSort::Update
+        ),
+        "/list/japan_rexue_shaonian_wanjie/update.html"
+    );
+    assert_eq!(
+        category_path(None, None, None, Some(SeriesStatus::Lianzai), ListSort::Index),
+        "/list/lianzai/"
+    );
+
+    assert_eq!(rank_path(RankPeriod::Day), "/rank/");
+    assert_eq!(rank_path(RankPeriod::Week), "/rank/week.html");
+
+    // The English aliases reach the same slugs.
+    match Args::try_parse_from(["mhg_dl_rs", "browse", "category", "--status", "completed"])
+        .unwrap()
+        .command
+    {
+        Some(Command::Browse { listing: BrowseListing::Category { status, .. } }) => {
+            assert!(matches!(status, Some(SeriesStatus::Wanjie)));
+        }
+        _ => panic!("expected browse category"),
+    }
+}
+
+#[test]
+fn test_search_result_details() {
+    // One hit as the search page lays it out: the cover link carries the title
+    // and a lazy-loaded, protocol-relative cover; the details are labelled
+    // lines, with the status line written as prose.
+    let html = r#"
+        <div class="book-result"><ul>
+            <li class="cf">
+                <div class="book-cover fl"><a class="bcover" href="/comic/54544/" title="金田一爸爸事件簿">
+                    <img src="//cf.mhgui.com/images/loading.gif" data-src="//cf.mhgui.com/cpic/h/54544.jpg"></a></div>
+                <div class="book-detail"><dl>
+                    <dt><a href="/comic/54544/" title="金田一爸爸事件簿">金田一爸爸事件簿</a></dt>
+                    <dd class="tags status"><span><strong>狀態</strong>：<span class="red">連載</span>。
+                        最近於 [<span class="red">2024-03-01</span>] 更新至
+                        [ <a href="/comic/54544/700.html" class="blue">第30話</a> ]</span></dd>
+                    <dd class="tags"><span><strong>別名</strong>：<a href="/comic/54544/">金田一37歲事件簿</a></span></dd>
+                    <dd class="tags"><span><strong>作者</strong>：<a href="/author/1/">天樹征丸</a>、<a href="/author/2/">佐藤文也</a></span></dd>
+                </dl></div>
+            </li>
+        </ul></div>
+    "#;
+    let (results, _) = parse_search_results(html);
+    assert_eq!(results.len(), 1);
+    let r = &results[0];
+    assert_eq!(r.title, "金田一爸爸事件簿");
+    assert_eq!(r.comic_id, 54544);
+    assert_eq!(r.authors, vec!["天樹征丸", "佐藤文也"]);
+    assert_eq!(r.aliases, vec!["金田一37歲事件簿"]);
+    assert_eq!(r.status.as_deref(), Some("連載"));
+    assert_eq!(r.last_update.as_deref(), Some("2024-03-01"));
+    assert_eq!(r.latest_chapter.as_deref(), Some("第30話"));
+    assert_eq!(r.cover_url.as_deref(), Some("https://cf.mhgui.com/cpic/h/54544.jpg"));
+    assert!(r.describe().starts_with("金田一爸爸事件簿 [連載] 天樹征丸/佐藤文也 — 第30話 (2024-03-01)"));
+}
+
+#[test]
+fn test_result_filter() {
+    let result = |title: &str, status: Option<&str>, date: Option<&str>, author: &str| SearchResult {
+        title: title.to_string(),
+        status: status.map(String::from),
+        last_update: date.map(String::from),
+        authors: vec![author.to_string()],
+        ..SearchResult::default()
+    };
+    let mut results = vec![
+        result("B", Some("連載"), Some("2024-01-01"), "Oda"),
+        result("A", Some("完結"), Some("2023-05-05"), "Toriyama"),
+        result("C", None, Some("2024-06-01"), "Oda Eiichiro"),
+    ];
+
+    let filter = ResultFilter { status: Some(SeriesStatus::Wanjie), author: None, order: ResultOrder::Site };
+    let kept: Vec<&str> = results.iter().filter(|r| filter.keeps(r)).map(|r| r.title.as_str()).collect();
+    // A result with no status at all cannot be shown to match.
+    assert_eq!(kept, vec!["A"]);
+
+    let filter = ResultFilter { status: None, author: Some("oda".to_string()), order: ResultOrder::Updated };
+    let kept: Vec<&str> = results.iter().filter(|r| filter.keeps(r)).map(|r| r.title.as_str()).collect();
+    assert_eq!(kept, vec!["B", "C"]);
+
+    filter.sort(&mut results);
+    let order: Vec<&str> = results.iter().map(|r| r.title.as_str()).collect();
+    assert_eq!(order, vec!["C", "B", "A"]);
+
+    ResultFilter { status: None, author: None, order: ResultOrder::Title }.sort(&mut results);
+    let order: Vec<&str> = results.iter().map(|r| r.title.as_str()).collect();
+    assert_eq!(order, vec!["A", "B", "C"]);
+}
+
+#[test]
+fn test_chapter_selection() {
+    use tui::ChapterSelection;
+    let mut sel = ChapterSelection::new(vec![true, false, false, true, false]);
+    assert!(sel.indices().is_empty());
+
+    // A range runs from the last toggled chapter, in either direction.
+    sel.toggle(3);
+    sel.select_range(1);
+    assert_eq!(sel.indices(), vec![1, 2, 3]);
+    sel.toggle(2);
+    assert_eq!(sel.indices(), vec![1, 3]);
+    sel.select_range(4);
+    assert_eq!(sel.indices(), vec![1, 2, 3, 4]);
+
+    sel.select_missing();
+    assert_eq!(sel.indices(), vec![1, 2, 4]);
+    sel.clear();
+    assert!(!sel.is_selected(1));
+    sel.select_all();
+    assert_eq!(sel.indices(), vec![0, 1, 2, 3, 4]);
+
+    // Without an anchor, a range is the one chapter under the cursor.
+    let mut sel = ChapterSelection::new(vec![false; 3]);
+    sel.select_range(2);
+    assert_eq!(sel.indices(), vec![2]);
+}
+
+#[test]
+fn test_scroll_window() {
+    use tui::scroll_window;
+    let mut top = 0;
+    assert_eq!(scroll_window(&mut top, 0, 3, 10), 0..3);
+    // Moving inside the window leaves it where it is.
+    assert_eq!(scroll_window(&mut top, 4, 20, 5), 0..5);
+    // Moving past either edge scrolls just far enough.
+    assert_eq!(scroll_window(&mut top, 7, 20, 5), 3..8);
+    assert_eq!(scroll_window(&mut top, 5, 20, 5), 3..8);
+    assert_eq!(scroll_window(&mut top, 1, 20, 5), 1..6);
+    // A list that shrank under the window pulls it back up.
+    top = 15;
+    assert_eq!(scroll_window(&mut top, 2, 4, 5), 0..4);
+}
+
+#[test]
+fn test_chapter_name_filter() {
+    let chapters = chapters_named(&[
+        ("單行本", "第01卷"),
+        ("單行本", "第02卷"),
+        ("單話", "第10話"),
+        ("單話", "第11話 番外"),
+        ("番外篇", "特別篇"),
+    ]);
+    let kept = |args: &[&str]| -> Vec<usize> {
+        let args = Args::try_parse_from([&["mhg_dl_rs", "download", "1"], args].concat()).unwrap();
+        let Some(Command::Download { select, .. }) = args.command else { panic!("not a download") };
+        (0..chapters.len()).filter(|&i| select.names.keeps(&chapters[i])).collect()
+    };
+    assert_eq!(kept(&[]), vec![0, 1, 2, 3, 4]);
+    assert_eq!(kept(&["--match", "話"]), vec![2, 3]);
+    assert_eq!(kept(&["--match", "話", "--exclude", "番外"]), vec![2]);
+    assert_eq!(kept(&["--group", "^單行本$"]), vec![0, 1]);
+    // The exclusion looks at names only, not at the group heading.
+    assert_eq!(kept(&["--exclude", "番外"]), vec![0, 1, 2, 4]);
+
+    // A pattern that does not compile is a usage error, not a silent no-match.
+    assert!(Args::try_parse_from(["mhg_dl_rs", "download", "1", "--match", "("]).is_err());
+    // --json prints results and downloads nothing, so it takes no chapters.
+    assert!(Args::try_parse_from(["mhg_dl_rs", "search", "x", "--json", "--match", "a"]).is_err());
+}
+
+#[test]
+fn test_prompt_for_chapters_by_name() {
+    let chapters = chapters_named(&[("單話", "第01話"), ("單話", "第02話"), ("番外篇", "番外 01")]);
+    assert_eq!(parse_chapter_pattern("/第0", &chapters), Some(vec![0, 1]));
+    assert_eq!(parse_chapter_pattern("/番外/", &chapters), Some(vec![2]));
+    assert_eq!(parse_chapter_pattern("/nothing", &chapters), None);
+    assert_eq!(parse_chapter_pattern("/(", &chapters), None);
+    assert_eq!(parse_chapter_pattern("1-2", &chapters), None);
+
+    // The matches are confirmed before they count; declining asks again.
+    let mut input = std::io::Cursor::new("/nothing\n/第0\nn\n/番外\n\n");
+    assert_eq!(prompt_for_chapters(&mut input, &chapters).unwrap(), vec![2]);
+    // Numbers are taken as they are, without a confirmation.
+    let mut input = std::io::Cursor::new("3\n");
+    assert_eq!(prompt_for_chapters(&mut input, &chapters).unwrap(), vec![2]);
+}
+
+#[test]
+fn test_image_stem() {
+    assert_eq!(pages::image_stem("00_001.jpg.webp"), "00_001");
+    assert_eq!(pages::image_stem("07_P_07.WEBP"), "07_P_07");
+    assert_eq!(pages::image_stem("00_001"), "00_001");
+    // Only image extensions go; a dot elsewhere in the name stays.
+    assert_eq!(pages::image_stem("00_v1.5.webp"), "00_v1.5");
+}
+
+#[test]
+fn test_webp_pages_are_transcoded() {
+    use image::{codecs::webp::WebPEncoder, GenericImageView, ImageEncoder, RgbaImage};
+    let dir = TempDir::new().unwrap();
+    let pixels = RgbaImage::from_fn(6, 4, |x, y| image::Rgba([(x * 40) as u8, (y * 60) as u8, 128, 255]));
+    let mut webp = Vec::new();
+    WebPEncoder::new_lossless(&mut webp)
+        .write_image(pixels.as_raw(), 6, 4, image::ExtendedColorType::Rgba8)
+        .unwrap();
+    // A WebP under its own name, one served as ".jpg", and a page that is
+    // not WebP at all.
+    fs::write(dir.path().join("0_a.jpg.webp"), &webp).unwrap();
+    fs::write(dir.path().join("1_b.jpg"), &webp).unwrap();
+    fs::write(dir.path().join("2_c.jpg"), b"\xFF\xD8\xFF\xE0 not decoded").unwrap();
+    let names = || vec!["0_a.jpg.webp".to_string(), "1_b.jpg".to_string(), "2_c.jpg".to_string()];
+
+    let untouched = PageOptions::default().apply(dir.path(), names()).unwrap();
+    assert_eq!(untouched, names());
+
+    let jpeg = PageOptions { webp_to: Some(pages::WebpTarget::Jpeg), jpeg_quality: 80, ..PageOptions::default() };
+    let packed = jpeg.apply(dir.path(), names()).unwrap();
+    assert_eq!(packed, vec!["processed/0_a.jpg", "processed/1_b.jpg", "2_c.jpg"]);
+    for name in &packed[..2] {
+        let bytes = fs::read(dir.path().join(name)).unwrap();
+        assert!(bytes.starts_with(b"\xFF\xD8\xFF"), "{name} was not re-encoded");
+        assert_eq!(image::load_from_memory(&bytes).unwrap().dimensions(), (6, 4));
+    }
+    // The originals stay until the chapter directory goes, for a resumed run.
+    assert!(dir.path().join("0_a.jpg.webp").exists());
+    assert!(is_webp(&fs::read(dir.path().join("1_b.jpg")).unwrap()));
+
+    let png = PageOptions { webp_to: Some(pages::WebpTarget::Png), ..PageOptions::default() };
+    assert_eq!(png.apply(dir.path(), vec!["0_a.jpg.webp".to_string()]).unwrap(), vec!["processed/0_a.png"]);
+    let decoded = image::open(dir.path().join("processed/0_a.png")).unwrap();
+    assert_eq!(decoded.to_rgba8(), pixels);
+}
+
+#[test]
+fn test_page_adjustments() {
+    use image::{DynamicImage, GenericImageView, RgbImage};
+    let page = || DynamicImage::ImageRgb8(RgbImage::from_pixel(2000, 1000, image::Rgb([200, 100, 50])));
+    let fitted = |args: &[&str]| -> PageOptions {
+        let args = Args::try_parse_from([&["mhg_dl_rs", "1"], args].concat()).unwrap();
+        args.download.pages
+    };
+
+    // Fitted inside the screen with the aspect ratio kept, never enlarged.
+    let small = fitted(&["--screen", "1000x1400"]).adjust(page());
+    assert_eq!(small.dimensions(), (1000, 500));
+    let kindle = fitted(&["--device", "kindle-paperwhite"]);
+    assert_eq!(kindle.adjust(small).dimensions(), (1000, 500));
+
+    let gray = fitted(&["--grayscale"]).adjust(page());
+    assert_eq!(gray.color(), image::ColorType::L8);
+    let level = gray.to_luma8().get_pixel(0, 0).0[0];
+    // Gamma above 1 darkens, below 1 lightens.
+    let darker = fitted(&["--grayscale", "--gamma", "1.8"]).adjust(page());
+    assert!(darker.to_luma8().get_pixel(0, 0).0[0] < level);
+    let lighter = fitted(&["--grayscale", "--gamma", "0.5"]).adjust(page());
+    assert!(lighter.to_luma8().get_pixel(0, 0).0[0] > level);
+    let flatter = fitted(&["--grayscale", "--contrast", "-50"]).adjust(page());
+    assert!(flatter.to_luma8().get_pixel(0, 0).0[0] > level);
+
+    assert!(Args::try_parse_from(["mhg_dl_rs", "1", "--screen", "1072"]).is_err());
+    assert!(Args::try_parse_from(["mhg_dl_rs", "1", "--gamma", "0"]).is_err());
+    for contrast in ["NaN", "inf", "-101", "250"] {
+        assert!(Args::try_parse_from(["mhg_dl_rs", "1", "--contrast", contrast]).is_err(), "{contrast}");
+    }
+    assert!(Args::try_parse_from(["mhg_dl_rs", "1", "--device", "kobo-libra", "--screen", "10x10"]).is_err());
+}
+
+#[test]
+fn test_archive_entries_are_file_names() {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("processed")).unwrap();
+    fs::write(dir.path().join("processed/0_a.jpg"), b"a").unwrap();
+    fs::write(dir.path().join("1_b.jpg"), b"b").unwrap();
+    let zip_path = dir.path().join("out.cbz");
+    Comic::write_archive(dir.path(), &["processed/0_a.jpg".to_string(), "1_b.jpg".to_string()], &zip_path)
+        .unwrap();
+    let mut archive = zip::ZipArchive::new(fs::File::open(&zip_path).unwrap()).unwrap();
+    let entries: Vec<String> = (0..archive.len())
+        .map(|i| archive.by_index(i).unwrap().name().to_string())
+        .collect();
+    assert_eq!(entries, vec!["0_a.jpg", "1_b.jpg"]);
+}
+
+#[test]
+fn test_spreads() {
+    use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+    // A spread whose left half is black and right half white.
+    let spread = || {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(400, 300, |x, _| {
+            if x < 200 { Rgb([0, 0, 0]) } else { Rgb([255, 255, 255]) }
+        }))
+    };
+    let brightness = |page: &DynamicImage| page.to_luma8().get_pixel(0, 0).0[0];
+    let options = |args: &[&str]| -> PageOptions {
+        Args::try_parse_from([&["mhg_dl_rs", "1"], args].concat()).unwrap().download.pages
+    };
+
+    assert_eq!(options(&[]).lay_out(spread()).len(), 1);
+    // Right to left unless told otherwise: the right half is read first.
+    let rtl = options(&["--spreads", "split"]).lay_out(spread());
+    assert_eq!(rtl.iter().map(|p| p.dimensions()).collect::<Vec<_>>(), vec![(200, 300), (200, 300)]);
+    assert_eq!(rtl.iter().map(brightness).collect::<Vec<_>>(), vec![255, 0]);
+    let ltr = options(&["--spreads", "split", "--direction", "ltr"]).lay_out(spread());
+    assert_eq!(ltr.iter().map(brightness).collect::<Vec<_>>(), vec![0, 255]);
+
+    // Rotated so that the half read first is on top.
+    let rotated = options(&["--spreads", "rotate"]).lay_out(spread());
+    assert_eq!(rotated[0].dimensions(), (300, 400));
+    assert_eq!(brightness(&rotated[0]), 255);
+    let rotated = options(&["--spreads", "rotate", "--direction", "ltr"]).lay_out(spread());
+    assert_eq!(brightness(&rotated[0]), 0);
+
+    // Portrait pages are never touched.
+    let page = DynamicImage::ImageRgb8(RgbImage::new(300, 400));
+    assert_eq!(options(&["--spreads", "split"]).lay_out(page)[0].dimensions(), (300, 400));
+
+    // A split spread packs as two pages in place of one.
+    let dir = TempDir::new().unwrap();
+    let mut png = Vec::new();
+    spread().write_to(&mut io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+    fs::write(dir.path().join("3_x.png"), &png).unwrap();
+    let names = options(&["--spreads", "split"]).apply(dir.path(), vec!["3_x.png".to_string()]).unwrap();
+    assert_eq!(names, vec!["processed/3_x_1.jpg", "processed/3_x_2.jpg"]);
+}
+
+#[test]
+fn test_comic_info() {
+    let xml = pages::comic_info("金田一 <37歲>", "第01話 & more", pages::Direction::Rtl);
+    assert!(xml.contains("<Manga>YesAndRightToLeft</Manga>"), "{xml}");
+    assert!(xml.contains("<Series>金田一 &lt;37歲&gt;</Series>"), "{xml}");
+    assert!(xml.contains("<Title>第01話 &amp; more</Title>"), "{xml}");
+    assert!(pages::comic_info("a", "b", pages::Direction::Ltr).contains("<Manga>No</Manga>"));
+}
+
+/// A page-like test image: a gradient with a dark block whose position sets
+/// it apart from other `seed`s.
+fn test_page(seed: u32, width: u32, height: u32) -> image::DynamicImage {
+    image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |x, y| {
+        let block = (x * 4 / width + y * 4 / height * 4) == seed % 16;
+        let v = if block { 10 } else { (x * 255 / width) as u8 };
+        image::Rgb([v, v, v])
+    }))
+}
+
+#[test]
+fn test_blocklist() {
+    use pages::{page_hash, BlockEntry, Blocklist};
+    let credit = page_hash(&test_page(5, 800, 1200));
+    // The same page smaller and re-encoded still matches; another does not.
+    let mut jpeg = Vec::new();
+    test_page(5, 400, 600)
+        .write_to(&mut io::Cursor::new(&mut jpeg), image::ImageFormat::Jpeg)
+        .unwrap();
+    let again = page_hash(&image::load_from_memory(&jpeg).unwrap());
+    let other = page_hash(&test_page(10, 800, 1200));
+
+    let list = Blocklist::parse(&format!("# credits\n\n{credit:016x} credit page\n")).unwrap();
+    let hit = list.matching(again, 6).expect("re-encoded page should match");
+    assert_eq!(hit, &BlockEntry { hash: credit, note: "credit page".to_string() });
+    assert!(list.matching(other, 6).is_none());
+    assert_eq!(Blocklist::parse("ok\nnot-hex here").unwrap_err().0, 1);
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("conf/blocklist.txt");
+    assert!(Blocklist::load(&path).unwrap().is_empty());
+    Blocklist::append(&path, &BlockEntry { hash: credit, note: "a\nb".to_string() }).unwrap();
+    assert_eq!(Blocklist::load(&path).unwrap().matching(credit, 0).unwrap().note, "a b");
+}
+
+#[test]
+fn test_blocked_pages_are_dropped() {
+    let dir = TempDir::new().unwrap();
+    let list = dir.path().join("blocklist.txt");
+    let chapter = dir.path().join("chapter");
+    fs::create_dir(&chapter).unwrap();
+    for (i, seed) in [1, 2, 5].iter().enumerate() {
+        test_page(*seed, 200, 300).save(chapter.join(format!("{i}_p.png"))).unwrap();
+    }
+    let names: Vec<String> = (0..3).map(|i| format!("{i}_p.png")).collect();
+
+    // Blocked from the last page of an archive, the way a credit page is.
+    let cbz = dir.path().join("c.cbz");
+    Comic::write_archive(&chapter, &names, &cbz).unwrap();
+    assert!(archive_page(&cbz, 0).is_err());
+    assert!(archive_page(&cbz, 4).is_err());
+    assert_eq!(archive_page(&cbz, -1).unwrap().1, "2_p.png");
+    block_pages(std::slice::from_ref(&cbz), Some(-1), None, Some(&list)).unwrap();
+    assert!(block_pages(&[cbz], None, None, Some(&list)).is_err());
+
+    let args = Args::try_parse_from(["mhg_dl_rs", "1", "--drop-blocked", "--blocklist", list.to_str().unwrap()])
+        .unwrap();
+    let mut options = args.download.pages;
+    options.load_blocklist().unwrap();
+    assert_eq!(options.apply(&chapter, names.clone()).unwrap(), &names[..2]);
+
+    // Without --drop-blocked the list is not even read.
+    let mut options = PageOptions { blocklist: Some(list.clone()), ..PageOptions::default() };
+    options.load_blocklist().unwrap();
+    assert_eq!(options.apply(&chapter, names.clone()).unwrap(), names);
+
+    // Dropping every page is an error, not an empty archive.
+    let mut options = PageOptions { drop_blocked: true, blocklist: Some(list), ..PageOptions::default() };
+    options.load_blocklist().unwrap();
+    assert!(options.apply(&chapter, vec!["2_p.png".to_string()]).is_err());
+}
+
+fn encoded(image: &image::DynamicImage, format: image::ImageFormat) -> Vec<u8> {
+    let mut data = Vec::new();
+    image.write_to(&mut io::Cursor::new(&mut data), format).unwrap();
+    data
+}
+
+#[test]
+fn test_check_image() {
+    use validate::check_image;
+    let page = test_page(3, 120, 180);
+    let jpeg = encoded(&page, image::ImageFormat::Jpeg);
+    let webp = encoded(&page, image::ImageFormat::WebP);
+    assert_eq!(check_image(&jpeg, true), Ok((120, 180)));
+    assert_eq!(check_image(&webp, true), Ok((120, 180)));
+
+    // A JPEG cut off anywhere past its headers is still missing its EOI.
+    let err = check_image(&jpeg[..jpeg.len() * 2 / 3], false).unwrap_err();
+    assert!(err.contains("EOI"), "{err}");
+    assert!(check_image(&jpeg[..jpeg.len() - 2], false).is_err());
+    // Padding after EOI is tolerated.
+    let mut padded = jpeg.clone();
+    padded.extend_from_slice(&[0; 8]);
+    assert!(check_image(&padded, false).is_ok());
+
+    // A WebP shorter or longer than its RIFF header says.
+    assert!(check_image(&webp[..webp.len() - 10], false).is_err());
+    let mut long = webp.clone();
+    long.extend_from_slice(&[0; 4]);
+    assert!(check_image(&long, false).unwrap_err().contains("WebP header says"));
+
+    // The other formats, whole and cut short.
+    for format in [image::ImageFormat::Png, image::ImageFormat::Gif] {
+        let data = encoded(&page, format);
+        assert_eq!(check_image(&data, true), Ok((120, 180)), "{format:?}");
+        assert!(check_image(&data[..data.len() - 4], false).is_err(), "{format:?}");
+    }
+    let mut avif = b"\x00\x00\x00\x14ftypavif\x00\x00\x00\x00avif".to_vec();
+    avif.extend_from_slice(b"\x00\x00\x00\x20meta\x00\x00\x00\x00\x00\x00\x00\x14ispe\x00\x00\x00\x00");
+    avif.extend_from_slice(&[0, 0, 0, 120, 0, 0, 0, 180]);
+    avif.extend_from_slice(b"\x00\x00\x00\x10mdat12345678");
+    assert_eq!(check_image(&avif, true), Ok((120, 180)));
+    let err = check_image(&avif[..avif.len() - 1], false).unwrap_err();
+    assert!(err.contains("past the end"), "{err}");
+    // A 64-bit box size as large as it goes is damage, not an overflow.
+    let mut huge = avif[..avif.len() - 16].to_vec();
+    huge.extend_from_slice(b"\x00\x00\x00\x01mdat\xff\xff\xff\xff\xff\xff\xff\xff");
+    let err = check_image(&huge, false).unwrap_err();
+    assert!(err.contains("past the end"), "{err}");
+    // So is one too short to cover the 16 bytes of its own header.
+    let mut short = avif[..avif.len() - 16].to_vec();
+    short.extend_from_slice(b"\x00\x00\x00\x01mdat\x00\x00\x00\x00\x00\x00\x00\x08");
+    let err = check_image(&short, false).unwrap_err();
+    assert!(err.contains("shorter than its own header"), "{err}");
+
+    // Tracking pixels are not pages.
+    let pixel = encoded(&test_page(0, 1, 1), image::ImageFormat::Jpeg);
+    assert!(check_image(&pixel, false).unwrap_err().contains("too small"));
+
+    // Structurally whole but damaged inside: only a full decode notices.
+    let mut damaged = webp.clone();
+    let at = webp.len() - 40;
+    damaged[at..at + 20].fill(0xFF);
+    assert!(check_image(&damaged, false).is_ok());
+    assert!(check_image(&damaged, true).is_err());
+}
+
+#[test]
+fn test_damaged_page_is_fetched_again() {
+    use std::thread;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    let jpeg = encoded(&test_page(1, 64, 64), image::ImageFormat::Jpeg);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    // The first answer is cut short without a Content-Length, which only the
+    // structure check notices; the second is whole.
+    let bodies = [jpeg[..jpeg.len() / 2].to_vec(), jpeg.clone()];
+    let server = thread::spawn(move || {
+        for (body, stream) in bodies.iter().zip(listener.incoming()) {
+            let mut stream = stream.unwrap();
+            let _ = stream.read(&mut [0; 512]);
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n");
+            let _ = stream.write_all(body);
+        }
+    });
+
+    let temp_dir = TempDir::new().unwrap();
+    let comic = test_comic(&format!("http://127.0.0.1:{}", port), temp_dir.path());
+    let chap = test_chapter(&["test.jpg"]);
+    let names = comic
+        .http
+        .block_on(comic.download_images(0, &chap, temp_dir.path(), &ProgressBar::hidden(), "http://localhost/chapter"))
+        .unwrap();
+    assert_eq!(names, ["0_test.jpg"]);
+    assert_eq!(fs::read(temp_dir.path().join("0_test.jpg")).unwrap(), jpeg);
+    server.join().unwrap();
+}
+
+#[test]
+fn test_pages_are_saved_under_their_format() {
+    use std::thread;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    let webp = encoded(&test_page(1, 64, 64), image::ImageFormat::WebP);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let body = webp.clone();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = stream.read(&mut [0; 512]);
+        let head = "HTTP/1.1 200 OK\r\nContent-Type: image/jpeg\r\nConnection: close\r\n\r\n";
+        let _ = stream.write_all(head.as_bytes());
+        let _ = stream.write_all(&body);
+    });
+
+    // A WebP named as a JPEG, then served as one, is saved as what it is. The
+    // second page is already on disk under the name it got when it was saved.
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("1_002.png"), b"already here").unwrap();
+    let comic = test_comic(&format!("http://127.0.0.1:{}", port), temp_dir.path());
+    let chap = test_chapter(&["001.jpg", "002.jpg.webp"]);
+    let names = comic
+        .http
+        .block_on(comic.download_images(0, &chap, temp_dir.path(), &ProgressBar::hidden(), "http://localhost/chapter"))
+        .unwrap();
+    assert_eq!(names, ["0_001.webp", "1_002.png"]);
+    assert_eq!(fs::read(temp_dir.path().join("0_001.webp")).unwrap(), webp);
+    server.join().unwrap();
+
+    assert!(PageFormat::Jpeg.is_labelled("image/jpg"));
+    assert!(!PageFormat::WebP.is_labelled("image/jpeg"));
+}
+
+#[test]
+fn test_limit_rate() {
+    use throttle::parse_rate;
+    assert_eq!(parse_rate("500K"), Ok(500 * 1024));
+    assert_eq!(parse_rate("2M"), Ok(2 * 1024 * 1024));
+    assert_eq!(parse_rate("1.5m"), Ok(3 * 512 * 1024));
+    assert_eq!(parse_rate("800"), Ok(800));
+    for bad in ["", "K", "fast", "0", "-1M", "2MB"] {
+        assert!(parse_rate(bad).is_err(), "{bad}");
+    }
+
+    // 192 KiB at 256 KiB/s, with a quarter second of burst allowed up front.
+    let throttle = Throttle::new(&throttle::PaceOptions {
+        limit_rate: Some(256 * 1024),
+        ..Default::default()
+    });
+    let bar = ProgressBar::hidden();
+    let started = std::time::Instant::now();
+    /// A body that arrives in one piece, leaving the pacing to the throttle.
+    struct Whole(Option<Vec<u8>>);
+    impl transport::Body for Whole {
+        fn chunk(&mut self) -> transport::BoxFuture<'_, Result<Option<Vec<u8>>>> {
+            let chunk = self.0.take();
+            Box::pin(async move { Ok(chunk) })
+        }
+    }
+    let mut data = Vec::new();
+    runtime().unwrap().block_on(async {
+        let mut body = throttle.read(Box::new(Whole(Some(vec![7; 192 * 1024]))), &bar);
+        while let Some(chunk) = body.chunk().await.unwrap() {
+            data.extend_from_slice(&chunk);
+        }
+    });
+    assert_eq!(data.len(), 192 * 1024);
+    assert!(started.elapsed() >= Duration::from_millis(450), "{:?}", started.elapsed());
+    assert!(bar.prefix().ends_with("/s"));
+}
+
+#[test]
+fn test_requests_wait_their_turn() {
+    use std::time::Instant;
+    let throttle = Throttle::new(&PaceOptions { rpm: 600, site_rpm: 60, jitter: 0, ..Default::default() });
+    let tunnel = Url::parse("https://us.hamreus.com/ps3/001.jpg").unwrap();
+
+    // A tenth of a second between requests to the same host; none before the
+    // first.
+    let runtime = runtime().unwrap();
+    let started = Instant::now();
+    for _ in 0..3 {
+        runtime.block_on(throttle.wait_turn(&tunnel));
+    }
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(200) && elapsed < Duration::from_millis(600), "{elapsed:?}");
+
+    // Each tunnel and the site are paced on their own.
+    let started = Instant::now();
+    runtime.block_on(throttle.wait_turn(&Url::parse("https://eu.hamreus.com/ps3/001.jpg").unwrap()));
+    runtime.block_on(throttle.wait_turn(&HOST_URL));
+    assert!(started.elapsed() < Duration::from_millis(50));
+}
+
+#[test]
+fn test_delay_ms_comes_to_a_rate() {
+    let pace = |args: &[&str]| {
+        let args = Args::try_parse_from([&["mhg_dl_rs"], args, &["1"]].concat()).unwrap();
+        args.download.pace.image_rpm()
+    };
+    assert_eq!(pace(&[]), 60);
+    assert_eq!(pace(&["--rpm", "90"]), 90);
+    // The old option still parses, as the rate it comes to.
+    assert_eq!(pace(&["-d", "500"]), 120);
+    assert_eq!(pace(&["--delay-ms", "0"]), 60_000);
+    assert_eq!(pace(&["--delay-ms", "120000"]), 1);
+    assert!(Args::try_parse_from(["mhg_dl_rs", "-d", "500", "--rpm", "90", "1"]).is_err());
+}
+
+#[test]
+fn test_throttled_requests_slow_down_and_retry() {
+    use std::cell::RefCell;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let answers = [
+        "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+    ];
+    let server = thread::spawn(move || {
+        for (answer, stream) in answers.iter().zip(listener.incoming()) {
+            let mut stream = stream.unwrap();
+            let _ = stream.read(&mut [0; 512]);
+            let _ = stream.write_all(answer.as_bytes());
+        }
+    });
+
+    // 100 requests a second to start with; each throttling answer halves it.
+    let throttle = Throttle::new(&PaceOptions { rpm: 6000, site_rpm: 6000, jitter: 0, ..Default::default() });
+    let url = Url::parse(&format!("http://127.0.0.1:{port}/page")).unwrap();
+    let notices = RefCell::new(Vec::new());
+    let runtime = runtime().unwrap();
+    let resp = runtime
+        .block_on(throttle.send(&Client::new(), &Request::get(&url), |n| notices.borrow_mut().push(n)))
+        .unwrap();
+    assert_eq!(runtime.block_on(resp.text()).unwrap(), "ok");
+    server.join().unwrap();
+
+    let notices = notices.into_inner();
+    assert_eq!(notices.len(), 2, "{notices:?}");
+    assert!(notices[0].contains("answered 429") && notices[0].contains("slowing to 3000"), "{}", notices[0]);
+    assert!(notices[1].contains("answered 503") && notices[1].contains("slowing to 1500"), "{}", notices[1]);
+}
+
+#[test]
+fn test_long_retry_after_fails_at_once() {
+    use std::cell::RefCell;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = stream.read(&mut [0; 512]);
+        let answer = "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 86400\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+        let _ = stream.write_all(answer.as_bytes());
+    });
+
+    // A day's wait is not waited out, nor asked again after the cap.
+    let throttle = Throttle::new(&PaceOptions::default());
+    let url = Url::parse(&format!("http://127.0.0.1:{port}/page")).unwrap();
+    let notices = RefCell::new(Vec::new());
+    let started = std::time::Instant::now();
+    let (client, request) = (Client::new(), Request::get(&url));
+    let result = runtime().unwrap().block_on(throttle.send(&client, &request, |n| notices.borrow_mut().push(n)));
+    assert!(matches!(result, Err(AppError::Status(StatusCode::TOO_MANY_REQUESTS, _))), "{:?}", result.map(|_| ()));
+    assert!(started.elapsed() < Duration::from_secs(5));
+    server.join().unwrap();
+    let notices = notices.into_inner();
+    assert_eq!(notices.len(), 1, "{notices:?}");
+    assert!(notices[0].contains("86400s"), "{}", notices[0]);
+}
+
+#[test]
+fn test_cookie_jar() {
+    use reqwest::header::SET_COOKIE;
+
+    let site = Url::parse("https://tw.manhuagui.com/comic/1/").unwrap();
+    let tunnel = Url::parse("https://us.hamreus.com/ps3/001.jpg").unwrap();
+    let header = |jar: &CookieJar, url: &Url| {
+        jar.header_for(url).map(|v| v.to_str().unwrap().to_string()).unwrap_or_default()
+    };
+
+    // The site still gets its country before it has set anything.
+    let jar = CookieJar::default();
+    assert_eq!(header(&jar, &site), "country=TW");
+    assert_eq!(header(&jar, &tunnel), "");
+
+    // --accept-adult confirms the gate on the site only, over any saved answer.
+    let mut adult = CookieJar::default();
+    adult.accept_adult = true;
+    adult.import(".manhuagui.com\tTRUE\t/\tFALSE\t0\tisAdult\t0\n").unwrap();
+    assert_eq!(header(&adult, &site), "country=TW; isAdult=1");
+    assert_eq!(header(&adult, &tunnel), "");
+
+    // A browser export: only the site's and the image hosts' cookies are
+    // taken, HttpOnly ones included; expired ones are not.
+    let export = "# Netscape HTTP Cookie File\n\
+        #HttpOnly_.manhuagui.com\tTRUE\t/\tFALSE\t0\tlogin\tabc\n\
+        .manhuagui.com\tTRUE\t/\tFALSE\t4102444800\tcountry\tCN\n\
+        .hamreus.com\tTRUE\t/\tTRUE\t4102444800\tcdn\t1\n\
+        .manhuagui.com\tTRUE\t/\tFALSE\t1\told\tgone\n\
+        .example.com\tTRUE\t/\tFALSE\t0\tother\tx\n";
+    assert_eq!(jar.import(export).unwrap(), 3);
+    assert_eq!(header(&jar, &site), "login=abc; country=CN");
+    assert_eq!(header(&jar, &tunnel), "cdn=1");
+    // `cdn` is secure-only.
+    assert_eq!(header(&jar, &Url::parse("http://us.hamreus.com/a.jpg").unwrap()), "");
+
+    // Set-Cookie replaces, adds and deletes, and the jar is saved between runs.
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("cookies.txt");
+    let jar = CookieJar::load(Some(path.clone())).unwrap();
+    jar.import(export).unwrap();
+    let mut headers = HeaderMap::new();
+    for value in [
+        "country=TW; Domain=.manhuagui.com; Path=/; Max-Age=3600",
+        "login=; Domain=.manhuagui.com; Path=/; Max-Age=0",
+        "seen=1; Path=/comic",
+        "tracker=1; Domain=.example.com",
+    ] {
+        headers.append(SET_COOKIE, HeaderValue::from_static(value));
+    }
+    jar.store(&site, &headers).unwrap();
+    let jar = CookieJar::load(Some(path)).unwrap();
+    assert_eq!(header(&jar, &site), "country=TW; seen=1");
+    assert_eq!(header(&jar, &Url::parse("https://tw.manhuagui.com/s/a.html").unwrap()), "country=TW");
+    assert_eq!(header(&jar, &Url::parse("https://www.manhuagui.com/comic/1/").unwrap()), "country=TW");
+
+    assert!(cookies::parse_cookies_txt("<html>not cookies</html>").is_empty());
+}
+
+#[test]
+fn test_html_cache() {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    // The first fetch gets the page and its ETag; the revalidation is only
+    // answered with 304 if it sends that ETag back.
+    let server = thread::spawn(move || {
+        let mut requests = Vec::new();
+        for stream in listener.incoming().take(2) {
+            let mut stream = stream.unwrap();
+            let mut buf = [0; 2048];
+            let n = stream.read(&mut buf).unwrap();
+            let request = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+            let answer = if request.contains("if-none-match: \"v1\"") {
+                "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n"
+            } else {
+                "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: 4\r\nConnection: close\r\n\r\npage"
+            };
+            stream.write_all(answer.as_bytes()).unwrap();
+            requests.push(request);
+        }
+        requests
+    });
+
+    let dir = TempDir::new().unwrap();
+    let url = Url::parse(&format!("http://127.0.0.1:{port}/comic/1/")).unwrap();
+    let http = |opts: &CacheOptions| Http {
+        runtime: runtime().unwrap(),
+        transport: Box::new(Client::new()),
+        throttle: Throttle::default(),
+        cookies: CookieJar::default(),
+        cache: HtmlCache::in_dir(Some(dir.path().to_path_buf()), opts),
+    };
+    let fetch = |http: &Http, lifetime| {
+        http.block_on(fetch_html(http, &url, &HOST_URL, lifetime, |html| Ok(html.to_string()), |_| {})).unwrap()
+    };
+    let request = Request::get(&url);
+
+    // Fetched once, then answered from the cache while it is fresh.
+    let fresh = http(&CacheOptions::default());
+    assert_eq!(fetch(&fresh, Lifetime::Page), "page");
+    assert_eq!(fetch(&fresh, Lifetime::Page), "page");
+    assert!(matches!(fresh.cache.lookup(&request, Lifetime::Chapter), Some(Cached::Fresh(_))));
+
+    // With no time to live, a page is revalidated and a chapter page is not
+    // looked up at all; --refresh does not look anything up either.
+    let stale = http(&CacheOptions { cache_ttl: 0, ..Default::default() });
+    assert!(stale.cache.lookup(&request, Lifetime::Chapter).is_none());
+    assert_eq!(fetch(&stale, Lifetime::Page), "page");
+    let refresh = http(&CacheOptions { refresh: true, ..Default::default() });
+    assert!(refresh.cache.lookup(&request, Lifetime::Page).is_none());
+    // A TTL too long to count in seconds is as good as forever.
+    let forever = http(&CacheOptions { cache_ttl: u64::MAX, ..Default::default() });
+    assert!(matches!(forever.cache.lookup(&request, Lifetime::Page), Some(Cached::Fresh(_))));
+
+    let requests = server.join().unwrap();
+    assert!(!requests[0].contains("if-none-match"));
+    assert!(requests[1].contains("if-none-match"));
+}
+
+#[test]
+fn test_gated_page_is_fetched_again_with_accept_adult() {
+    use mock_site::{comic_page, Answer, MockSite};
+
+    let site = MockSite::start();
+    let url = "https://tw.manhuagui.com/comic/7";
+    site.route(
+        url,
+        Answer::html(r#"<html><body><div class="book-title"><h1>測試漫畫</h1></div>
+            <div class="warning-bar"><a href="javascript:;" id="checkAdult">繼續閱讀</a></div></body></html>"#),
+    );
+    let dir = TempDir::new().unwrap();
+    let open = |accept_adult| {
+        let mut http = site.http(&unpaced());
+        http.cookies.accept_adult = accept_adult;
+        http.cache = HtmlCache::in_dir(Some(dir.path().to_path_buf()), &CacheOptions::default());
+        Comic::new(7, http, dir.path(), 0, PageOptions::default())
+    };
+
+    assert!(matches!(open(false), Err(AppError::AdultGated(_))));
+    // The site shows the chapters once the gate is confirmed. The gate page
+    // was not kept, and the confirmation is part of what a page is kept
+    // under, so the rerun the error advises asks the site again.
+    site.route(url, Answer::html(comic_page("測試漫畫", &[("/comic/7/1.html", "第01話")])));
+    assert_eq!(open(true).unwrap().chapters.len(), 1);
+    assert_eq!(site.hits(url), 2);
+    // That page parsed, so it is kept, for the confirmed reader only.
+    assert_eq!(open(true).unwrap().chapters.len(), 1);
+    assert_eq!(site.hits(url), 2);
+    assert!(open(false).is_ok());
+    assert_eq!(site.hits(url), 3);
+}
+
+/// Pace options that let a mock site's requests through at once.
+fn unpaced() -> PaceOptions {
+    PaceOptions { rpm: 60_000, site_rpm: 60_000, jitter: 0, limit_rate: None, delay_ms: None }
+}
+
+#[test]
+fn test_search_to_archive_over_the_mock_site() {
+    use mock_site::{chapter_page, comic_page, search_page, Answer, MockSite};
+
+    let site = MockSite::start();
+    let page = encoded(&test_page(5, 120, 180), image::ImageFormat::Jpeg);
+    let tunnel = |chapter: usize, file: &str| format!("https://i.hamreus.com/ps1/t/7/{chapter}/{file}");
+    let results = search_page(&[(7, "測試漫畫"), (8, "別的")], None);
+    site.route("https://tw.manhuagui.com/s/test.html", Answer::html(results));
+    site.route(
+        "https://tw.manhuagui.com/comic/7",
+        Answer::html(comic_page(
+            "測試漫畫",
+            &[("/comic/7/1.html", "第01話"), ("/comic/7/2.html", "第02話"), ("/comic/7/3.html", "第03話")],
+        )),
+    );
+    for chapter in 1..=3 {
+        site.route(
+            &format!("https://tw.manhuagui.com/comic/7/{chapter}.html"),
+            Answer::html(chapter_page(&format!("/ps1/t/7/{chapter}/"), &["1.jpg", "2.jpg"])),
+        );
+    }
+    // Chapter 1 goes through although its host throttles once and its second
+    // page trickles in.
+    site.route(&tunnel(1, "1.jpg"), Answer::Throttled { times: 1, then: Box::new(Answer::image(page.clone())) });
+    site.route(&tunnel(1, "2.jpg"), Answer::Slow { body: page.clone(), delay: Duration::from_millis(2) });
+    // Chapter 2 is answered with a hotlink page instead of its first image;
+    // chapter 3's first image is cut short every time.
+    site.route(&tunnel(2, "1.jpg"), Answer::html("<html>no hotlinking</html>"));
+    site.route(&tunnel(2, "2.jpg"), Answer::image(page.clone()));
+    site.route(&tunnel(3, "1.jpg"), Answer::Truncated(page.clone()));
+    site.route(&tunnel(3, "2.jpg"), Answer::image(page.clone()));
+
+    let dir = TempDir::new().unwrap();
+    let out = dir.path().to_str().unwrap();
+    let args = Args::try_parse_from(["mhg_dl_rs", "search", "test", "--first", "-c", "1-3", "--no-tui", "-o", out]).unwrap();
+    let Some(Command::Search { keyword, pick, download }) = args.command else { panic!("not a search") };
+    let http = site.http(&unpaced());
+    // Two chapters of three fail, and so does the run.
+    let result = pick_and_download(http, Listing::search(&keyword).unwrap(), Some(&keyword), pick, download);
+    assert!(matches!(result, Err(AppError::ChaptersFailed(2, 3))), "{result:?}");
+
+    let book = dir.path().join("測試漫畫");
+    let mut archive = zip::ZipArchive::new(fs::File::open(book.join("測試漫畫_第01話.cbz")).unwrap()).unwrap();
+    let mut names: Vec<String> = archive.file_names().map(str::to_string).collect();
+    names.sort();
+    assert_eq!(names, ["0_1.jpg", "1_2.jpg"]);
+    let mut first = Vec::new();
+    archive.by_name("0_1.jpg").unwrap().read_to_end(&mut first).unwrap();
+    assert_eq!(first, page);
+    assert_eq!(site.hits(&tunnel(1, "1.jpg")), 2);
+
+    // A page that is not an image fails its chapter at once; one that keeps
+    // arriving cut short, after PAGE_ATTEMPTS tries. Neither gets an archive.
+    assert!(!book.join("測試漫畫_第02話.cbz").exists());
+    assert!(!book.join("測試漫畫_第03話.cbz").exists());
+    assert_eq!(site.hits(&tunnel(2, "1.jpg")), 1);
+    assert_eq!(site.hits(&tunnel(3, "1.jpg")), PAGE_ATTEMPTS);
+    assert_eq!(site.hits(&tunnel(2, "2.jpg")) + site.hits(&tunnel(3, "2.jpg")), 0);
+    // Only the picked comic was opened.
+    assert_eq!(site.hits("https://tw.manhuagui.com/comic/8"), 0);
+}
+
+#[test]
+fn test_unattended_listing_stops_paging() {
+    use mock_site::{search_page, Answer, MockSite};
+
+    // Four pages of two hits each, every page but the last offering the next.
+    let page_url = |n: usize| match n {
+        1 => "https://tw.manhuagui.com/s/test.html".to_string(),
+        n => format!("https://tw.manhuagui.com/s/test_p{n}.html"),
+    };
+    let fetched = |max_pages, enough, order| {
+        let site = MockSite::start();
+        for n in 1..=4 {
+            let next = (n < 4).then(|| format!("/s/test_p{}.html", n + 1));
+            let hits = [(n * 10, "甲"), (n * 10 + 1, "乙")];
+            site.route(&page_url(n), Answer::html(search_page(&hits, next.as_deref())));
+        }
+        let filter = ResultFilter { status: None, author: None, order };
+        let http = site.http(&unpaced());
+        let listing = Listing::search("test").unwrap();
+        let results = unattended_listing(&http, &listing, &filter, max_pages, enough).unwrap();
+        let hits: Vec<usize> = (1..=4).map(|n| site.hits(&page_url(n))).collect();
+        (results.len(), hits)
+    };
+
+    // --max-pages caps the pages fetched, and the pager ending stops earlier.
+    assert_eq!(fetched(2, None, ResultOrder::Site), (4, vec![1, 1, 0, 0]));
+    assert_eq!(fetched(5, None, ResultOrder::Site), (8, vec![1, 1, 1, 1]));
+    // --pick 3 has what it needs after two pages in the site's order...
+    assert_eq!(fetched(5, Some(3), ResultOrder::Site), (4, vec![1, 1, 0, 0]));
+    assert_eq!(fetched(5, Some(2), ResultOrder::Site), (2, vec![1, 0, 0, 0]));
+    // ...but not in another, where a later page may hold what sorts first.
+    assert_eq!(fetched(3, Some(1), ResultOrder::Title), (6, vec![1, 1, 1, 0]));
+}
+
+#[test]
+fn test_mock_site_answers() {
+    use mock_site::{Answer, MockSite};
+
+    let site = MockSite::start();
+    site.route("https://tw.manhuagui.com/a.html", Answer::html("<p>a</p>"));
+    let http = site.http(&unpaced());
+    let url = Url::parse("https://tw.manhuagui.com/a.html?x=1").unwrap();
+    let text = |html: &str| Ok(html.to_string());
+    assert_eq!(http.block_on(fetch_html(&http, &url, &HOST_URL, Lifetime::Page, text, |_| {})).unwrap(), "<p>a</p>");
+    // The query string does not pick the route, and unknown paths are 404s.
+    assert_eq!(site.hits("https://tw.manhuagui.com/a.html"), 1);
+    let missing = Url::parse("https://tw.manhuagui.com/b.html").unwrap();
+    match http.block_on(fetch_html(&http, &missing, &HOST_URL, Lifetime::Page, text, |_| {})) {
+        Err(AppError::Status(status, url)) => {
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(url, missing);
+        }
+        other => panic!("expected a 404, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_log_levels_and_timestamps() {
+    use log::LevelFilter;
+    use logging::timestamp;
+    let opts = |verbose, quiet| LogOptions { verbose, quiet, log_file: None };
+    assert_eq!(opts(0, false).terminal_level(), LevelFilter::Info);
+    assert_eq!(opts(1, false).terminal_level(), LevelFilter::Debug);
+    assert_eq!(opts(2, false).terminal_level(), LevelFilter::Trace);
+    assert_eq!(opts(0, true).terminal_level(), LevelFilter::Error);
+    // The file has every request, however quiet the terminal is.
+    assert_eq!(opts(0, true).file_level(), LevelFilter::Debug);
+    assert_eq!(opts(2, false).file_level(), LevelFilter::Trace);
+
+    let at = |secs, millis| std::time::UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis);
+    assert_eq!(timestamp(at(0, 0)), "1970-01-01T00:00:00.000Z");
+    assert_eq!(timestamp(at(951_782_400, 5)), "2000-02-29T00:00:00.005Z");
+    assert_eq!(timestamp(at(1_700_000_000, 123)), "2023-11-14T22:13:20.123Z");
+}
+
+#[test]
+fn test_progress_events() {
+    use progress::Event;
+    let json = |event: Event| serde_json::to_value(event).unwrap();
+    assert_eq!(
+        json(Event::PageDownloaded { chapter: 2, index: 3, total: 20, bytes: 4096 }),
+        serde_json::json!({"event": "page_downloaded", "chapter": 2, "index": 3, "total": 20, "bytes": 4096})
+    );
+    assert_eq!(
+        json(Event::ChapterSkipped { chapter: 1, name: "第01話", path: Path::new("Downloads/X/X_第01話.cbz") }),
+        serde_json::json!({"event": "chapter_skipped", "chapter": 1, "name": "第01話", "path": "Downloads/X/X_第01話.cbz"})
+    );
+    let failed = json(Event::ChapterFailed { chapter: 5, name: "第05話", error: AppError::Interrupted.to_string() });
+    assert_eq!(failed["event"], "chapter_failed");
+    assert_eq!(failed["error"], "Interrupted by Ctrl+C");
+}
+
+#[test]
+fn test_run_summary_and_verdict() {
+    let failed = || Err(AppError::Interrupted);
+    let mut summary = Summary::new();
+    assert!(summary.lines(&Tally::default()).is_empty(), "nothing to say about an empty run");
+    summary.record(0, "第01話", &Ok(true));
+    summary.record(1, "第02話", &Ok(false));
+    summary.record(4, "第05話", &failed());
+
+    let tally = Tally::default();
+    tally.page(1024);
+    tally.page(2048);
+    let lines = summary.lines(&tally);
+    assert!(lines[0].contains("1 downloaded, 1 skipped, 1 failed; 2 page(s), 3.00 KiB"), "{}", lines[0]);
+    assert_eq!(lines[1], "Failed chapter 5: 第05話");
+    let event = serde_json::to_value(summary.event(&tally)).unwrap();
+    assert_eq!(event["event"], "summary");
+    assert_eq!(event["failed"], serde_json::json!([{"chapter": 5, "name": "第05話"}]));
+    assert_eq!(event["bytes"], 3072);
+
+    // Some chapters failed: an error unless only a total failure counts.
+    assert!(matches!(summary.verdict(FailOn::Any), Err(AppError::ChaptersFailed(1, 3))));
+    assert!(summary.verdict(FailOn::All).is_ok());
+    let mut all_failed = Summary::new();
+    all_failed.record(0, "第01話", &failed());
+    assert!(all_failed.verdict(FailOn::All).is_err());
+    assert!(Summary::new().verdict(FailOn::All).is_ok());
+}
+
+#[test]
+fn test_exit_codes() {
+    let url = Url::parse("https://tw.manhuagui.com/comic/1/").unwrap();
+    let timed_out = io::Error::new(io::ErrorKind::TimedOut, "no answer from the server in time");
+    let codes = [
+        (AppError::InvalidUrl, 3),
+        (AppError::InvalidSelection("chapters '9'".into()), 4),
+        (AppError::NoSearchResults("金田一".into()), 5),
+        (AppError::Status(StatusCode::NOT_FOUND, url), 6),
+        (AppError::Io(timed_out), 6),
+        (AppError::ContentParsing("No chapters found".into()), 7),
+        (AppError::AdultGated("This chapter".into()), 8),
+        (AppError::Io(io::Error::new(io::ErrorKind::PermissionDenied, "read-only")), 9),
+        (AppError::Zip(zip::result::ZipError::FileNotFound), 9),
+        (AppError::SerdeJson(serde_json::Error::io(io::Error::other("closed pipe"))), 9),
+        (AppError::BadImage("truncated".into()), 10),
+        (AppError::ChaptersFailed(1, 3), 11),
+        (AppError::VerifyFailed(2), 12),
+        (AppError::Interrupted, 130),
+    ];
+    for (error, code) in codes {
+        assert_eq!(error.exit_code(), code, "{error}");
+    }
+
+    // A chapter page whose packed data does not read is a layout change too.
+    let page = |a: &str, payload: &str| format!("}}('SMH.imgData({payload}).preInit();',{a},0,'Q===')");
+    for html in [page("99999999999999999999999", "{}"), page("62", r#"{"files": 1}"#)] {
+        let err = Comic::parse_chapter_html(&html).unwrap_err();
+        assert_eq!(err.exit_code(), 7, "{err}");
+    }
+}
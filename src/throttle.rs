@@ -0,0 +1,439 @@
+//! Pacing for everything sent to the site and its image hosts: how many
+//! requests each host gets a minute, how that slows down when a host says it
+//! is being asked too often, the `--limit-rate` bandwidth cap, and the
+//! throughput shown next to each chapter's progress.
+//!
+//! One `Throttle` serves a whole job. Every request waits its turn with it and
+//! every page body is read through it, so the limits hold however many
+//! chapters or comics are queued, and for downloads running side by side as
+//! well.
+
+use crate::{
+    transport::{Body, Reply, Request, Transport},
+    HOST_URL,
+};
+use clap::builder::RangedU64ValueParser;
+use indicatif::{HumanBytes, ProgressBar};
+use rand::Rng;
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    StatusCode, Url,
+};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Largest piece of a response body counted against the cap at a time. Small
+/// enough that a capped download sleeps in short, even steps instead of
+/// bursting a whole page and then stalling.
+const CHUNK: usize = 16 * 1024;
+
+/// How long the throughput is averaged over before the shown figure changes.
+const METER_WINDOW: Duration = Duration::from_secs(1);
+
+/// Requests a minute to each image host unless `--rpm` says otherwise: a page
+/// a second, as the old fixed delay between pages had it.
+const DEFAULT_RPM: u32 = 60;
+
+/// Requests a minute to the site's own pages unless `--site-rpm` says
+/// otherwise. These are the requests the site watches most closely.
+const DEFAULT_SITE_RPM: u32 = 20;
+
+/// How far each wait is varied either way unless `--jitter` says otherwise.
+const DEFAULT_JITTER: u32 = 50;
+
+/// How many times a request answered with 429 or 503 is sent in all before
+/// the answer is taken as an error.
+const THROTTLED_ATTEMPTS: usize = 5;
+
+/// How far a host that keeps saying it is asked too often is slowed down: its
+/// interval doubles with each such answer, up to this many times the one it
+/// started at.
+const MAX_SLOWDOWN: u32 = 16;
+
+/// Successful requests in a row after which a slowed-down host's interval is
+/// halved again.
+const RECOVER_AFTER: u32 = 20;
+
+/// Throttling answers in a row, from any host, after which the whole job
+/// pauses rather than only the host that answered.
+const PAUSE_AFTER: u32 = 3;
+
+/// How long the whole job pauses for, unless a `Retry-After` asks for longer.
+const JOB_PAUSE: Duration = Duration::from_secs(60);
+
+/// Longest `Retry-After` waited out. Anything longer is a server telling us to
+/// go away for the day, which is better answered by failing the chapter than
+/// by hanging, so `Throttle::send` returns the status at once.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/// How fast the site and its image hosts are asked for things.
+#[derive(Clone, Debug, clap::Args)]
+#[clap(next_help_heading = "PACING")]
+pub(crate) struct PaceOptions {
+    /// Requests per minute to the image host
+    #[clap(long, value_name = "N", default_value_t = DEFAULT_RPM, value_parser = RangedU64ValueParser::<u32>::new().range(1..))]
+    pub(crate) rpm: u32,
+    /// Requests per minute to the site's comic, chapter and listing pages
+    #[clap(long, value_name = "N", default_value_t = DEFAULT_SITE_RPM, value_parser = RangedU64ValueParser::<u32>::new().range(1..))]
+    pub(crate) site_rpm: u32,
+    /// Vary each wait between requests by up to this many percent either way,
+    /// so that they do not arrive on a fixed beat
+    #[clap(long, value_name = "PERCENT", default_value_t = DEFAULT_JITTER, value_parser = RangedU64ValueParser::<u32>::new().range(0..=100))]
+    pub(crate) jitter: u32,
+    /// Cap the download speed of pages, in bytes per second (500K, 2M)
+    #[clap(long, value_name = "RATE", value_parser = parse_rate)]
+    pub(crate) limit_rate: Option<u64>,
+    /// The pause between pages in milliseconds, from before there was --rpm;
+    /// kept so that scripts passing it still run, as the rate it comes to
+    #[clap(short, long, value_name = "MS", hide = true, conflicts_with = "rpm")]
+    pub(crate) delay_ms: Option<u64>,
+}
+
+impl Default for PaceOptions {
+    fn default() -> Self {
+        PaceOptions {
+            rpm: DEFAULT_RPM,
+            site_rpm: DEFAULT_SITE_RPM,
+            jitter: DEFAULT_JITTER,
+            limit_rate: None,
+            delay_ms: None,
+        }
+    }
+}
+
+impl PaceOptions {
+    /// Requests per minute to the image host: `--rpm`, or one request per
+    /// `--delay-ms` if that was given instead.
+    pub(crate) fn image_rpm(&self) -> u32 {
+        match self.delay_ms {
+            Some(ms) => (60_000 / ms.max(1)).clamp(1, u32::MAX as u64) as u32,
+            None => self.rpm,
+        }
+    }
+}
+
+/// Parse a `--limit-rate` value: bytes per second, with an optional `K`, `M`
+/// or `G` suffix counting in 1024s as curl and wget do (`500K`, `1.5M`).
+pub(crate) fn parse_rate(s: &str) -> Result<u64, String> {
+    let (number, scale) = match s.char_indices().last() {
+        Some((at, 'k' | 'K')) => (&s[..at], 1 << 10),
+        Some((at, 'm' | 'M')) => (&s[..at], 1 << 20),
+        Some((at, 'g' | 'G')) => (&s[..at], 1 << 30),
+        _ => (s, 1),
+    };
+    let rate = number
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite())
+        .map(|n| n * scale as f64)
+        .ok_or_else(|| format!("'{s}' is not a rate; expected bytes per second such as 500K or 2M"))?;
+    if rate < 1.0 {
+        return Err("the rate must be at least one byte per second".to_string());
+    }
+    Ok(rate as u64)
+}
+
+pub(crate) struct Throttle {
+    /// `None` lets requests through as they come.
+    turns: Option<Turns>,
+    /// `None` reads at whatever speed the connection gives.
+    bucket: Option<Mutex<Bucket>>,
+    meter: Mutex<Meter>,
+}
+
+/// Leaves everything unpaced and only measures: for the one-request commands,
+/// and for tests that talk to a local server.
+impl Default for Throttle {
+    fn default() -> Self {
+        Throttle { turns: None, bucket: None, meter: Mutex::new(Meter::new()) }
+    }
+}
+
+impl Throttle {
+    pub(crate) fn new(opts: &PaceOptions) -> Self {
+        if opts.delay_ms.is_some() {
+            log::warn!("--delay-ms is deprecated and will be removed; use --rpm {} instead", opts.image_rpm());
+        }
+        let every = |rpm: u32| Duration::from_secs(60) / rpm;
+        Throttle {
+            turns: Some(Turns {
+                site: every(opts.site_rpm),
+                images: every(opts.image_rpm()),
+                jitter: opts.jitter as f64 / 100.0,
+                state: Mutex::new(TurnState::default()),
+            }),
+            bucket: opts.limit_rate.map(|rate| Mutex::new(Bucket::new(rate))),
+            meter: Mutex::new(Meter::new()),
+        }
+    }
+
+    /// Wait until `url`'s host may be sent another request. The first request
+    /// to a host goes at once.
+    pub(crate) async fn wait_turn(&self, url: &Url) {
+        let Some(turns) = &self.turns else { return };
+        let wait = turns.take(url.host_str().unwrap_or_default());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Send `request` over `transport` when it is its host's turn, and again
+    /// after each answer saying the host is asked too often — 429 or 503 — up
+    /// to `THROTTLED_ATTEMPTS` times in all. Any other error status is returned
+    /// as an error, and so is a throttling answer whose `Retry-After` is longer
+    /// than `MAX_RETRY_AFTER`. `notify` hears whenever the pace changes.
+    pub(crate) async fn send(
+        &self,
+        transport: &dyn Transport,
+        request: &Request,
+        notify: impl Fn(String),
+    ) -> crate::Result<Reply> {
+        let url = &request.url;
+        let host = url.host_str().unwrap_or_default();
+        let mut attempt = 1;
+        loop {
+            self.wait_turn(url).await;
+            let resp = transport.send(request).await?;
+            let Some(turns) = &self.turns else {
+                return resp.error_for_status();
+            };
+            let status = resp.status;
+            if !matches!(status, StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE) {
+                if let Some(notice) = turns.succeeded(host) {
+                    notify(notice);
+                }
+                return resp.error_for_status();
+            }
+            let wait = retry_after(&resp.headers);
+            if let Some(wait) = wait
+                && wait > MAX_RETRY_AFTER
+            {
+                notify(format!("{host} answered {status} and asks to wait {}s; not waiting that long", wait.as_secs()));
+                return resp.error_for_status();
+            }
+            notify(turns.throttled(host, status, wait));
+            if attempt == THROTTLED_ATTEMPTS {
+                return resp.error_for_status();
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Read `body` through the throttle, showing the throughput as `bar`'s
+    /// prefix.
+    ///
+    /// Only the reads are paced: the transport's timeout applies to each
+    /// piece rather than the whole body, so a capped download of a large page
+    /// does not run into it.
+    pub(crate) fn read<'a>(&'a self, body: Box<dyn Body>, bar: &'a ProgressBar) -> Throttled<'a> {
+        Throttled { body, throttle: self, bar }
+    }
+
+    /// Count `n` bytes read, sleeping for as long as the cap needs.
+    async fn transferred(&self, n: usize) {
+        self.meter.lock().unwrap().record(n as u64);
+        // Sleep outside the lock, so other downloads can take their turn.
+        let wait = self.bucket.as_ref().map_or(Duration::ZERO, |b| b.lock().unwrap().take(n as u64));
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// The current throughput, for a progress bar.
+    pub(crate) fn rate_text(&self) -> String {
+        format!("{}/s", HumanBytes(self.meter.lock().unwrap().rate))
+    }
+}
+
+pub(crate) struct Throttled<'a> {
+    body: Box<dyn Body>,
+    throttle: &'a Throttle,
+    bar: &'a ProgressBar,
+}
+
+impl Throttled<'_> {
+    /// The body's next piece, once the cap allows it; `None` at its end.
+    pub(crate) async fn chunk(&mut self) -> crate::Result<Option<Vec<u8>>> {
+        let Some(chunk) = self.body.chunk().await? else { return Ok(None) };
+        for piece in chunk.chunks(CHUNK) {
+            self.throttle.transferred(piece.len()).await;
+            self.bar.set_prefix(self.throttle.rate_text());
+        }
+        Ok(Some(chunk))
+    }
+}
+
+/// When each host may next be sent a request: a token bucket per host that
+/// holds a single request, so that requests are spaced out rather than
+/// allowed through in bursts. The site's own pages and each tunnel are
+/// separate hosts, paced separately.
+struct Turns {
+    site: Duration,
+    images: Duration,
+    /// Fraction of the interval each wait may vary by either way.
+    jitter: f64,
+    state: Mutex<TurnState>,
+}
+
+#[derive(Default)]
+struct TurnState {
+    hosts: HashMap<String, Host>,
+    /// Throttling answers since the last request that went through, from any
+    /// host.
+    strikes: u32,
+    /// Until when no host gets a turn, after `PAUSE_AFTER` strikes.
+    paused_until: Option<Instant>,
+}
+
+struct Host {
+    next: Instant,
+    /// What the host's interval is multiplied by, a power of two.
+    slowdown: u32,
+    /// Requests that went through since the last throttling answer or
+    /// recovery step.
+    streak: u32,
+}
+
+impl Turns {
+    fn interval(&self, host: &str) -> Duration {
+        if HOST_URL.host_str() == Some(host) { self.site } else { self.images }
+    }
+
+    /// Book the next turn at `host`, returning how long until it comes.
+    fn take(&self, host: &str) -> Duration {
+        let spread = rand::rng().random_range(1.0 - self.jitter..=1.0 + self.jitter);
+        let interval = self.interval(host);
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let paused_until = state.paused_until.unwrap_or(now);
+        let entry = state.host(host, now);
+        let turn = entry.next.max(now).max(paused_until);
+        entry.next = turn + interval.mul_f64(spread) * entry.slowdown;
+        turn - now
+    }
+
+    /// Slow `host` down after it answered `status`, and pause the whole job if
+    /// this keeps happening. Returns what changed, for the user.
+    fn throttled(&self, host: &str, status: StatusCode, retry_after: Option<Duration>) -> String {
+        let interval = self.interval(host);
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.strikes += 1;
+        let entry = state.host(host, now);
+        entry.slowdown = (entry.slowdown * 2).min(MAX_SLOWDOWN);
+        entry.streak = 0;
+        let slowed = interval * entry.slowdown;
+        let wait = retry_after.unwrap_or(slowed);
+        entry.next = entry.next.max(now + wait);
+        if state.strikes >= PAUSE_AFTER {
+            state.strikes = 0;
+            let pause = wait.max(JOB_PAUSE);
+            state.paused_until = Some(state.paused_until.unwrap_or(now).max(now + pause));
+            return format!("{host} keeps answering {status}; pausing all downloads for {}s", pause.as_secs());
+        }
+        format!(
+            "{host} answered {status}; waiting {}s, then slowing to {} requests a minute",
+            wait.as_secs_f64().ceil(),
+            per_minute(slowed)
+        )
+    }
+
+    /// Count a request to `host` that went through, speeding the host back up
+    /// a step after a run of them. Returns what changed, if anything.
+    fn succeeded(&self, host: &str) -> Option<String> {
+        let interval = self.interval(host);
+        let mut state = self.state.lock().unwrap();
+        state.strikes = 0;
+        let entry = state.host(host, Instant::now());
+        if entry.slowdown == 1 {
+            return None;
+        }
+        entry.streak += 1;
+        if entry.streak < RECOVER_AFTER {
+            return None;
+        }
+        entry.slowdown /= 2;
+        entry.streak = 0;
+        Some(format!("{host} is keeping up again; back to {} requests a minute", per_minute(interval * entry.slowdown)))
+    }
+}
+
+impl TurnState {
+    fn host(&mut self, host: &str, now: Instant) -> &mut Host {
+        self.hosts
+            .entry(host.to_string())
+            .or_insert(Host { next: now, slowdown: 1, streak: 0 })
+    }
+}
+
+fn per_minute(interval: Duration) -> String {
+    format!("{:.0}", 60.0 / interval.as_secs_f64())
+}
+
+/// How long a response's `Retry-After` asks for, given as seconds or as a
+/// date. A date already past asks for no wait at all.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// A token bucket holding up to a quarter second of bytes. Reads may run it
+/// into debt; the debt is what the reader then sleeps off.
+struct Bucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Self {
+        let rate = rate as f64;
+        let burst = (rate / 4.0).max(CHUNK as f64);
+        Bucket { rate, burst, tokens: burst, last: Instant::now() }
+    }
+
+    /// Take `n` bytes' worth of tokens, returning how long to wait until the
+    /// bucket is out of debt.
+    fn take(&mut self, n: u64) -> Duration {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.burst) - n as f64;
+        self.last = now;
+        if self.tokens < 0.0 {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+/// Bytes per second over the last full `METER_WINDOW`.
+struct Meter {
+    start: Instant,
+    bytes: u64,
+    rate: u64,
+}
+
+impl Meter {
+    fn new() -> Self {
+        Meter { start: Instant::now(), bytes: 0, rate: 0 }
+    }
+
+    fn record(&mut self, n: u64) {
+        self.bytes += n;
+        let elapsed = self.start.elapsed();
+        if elapsed >= METER_WINDOW {
+            self.rate = (self.bytes as f64 / elapsed.as_secs_f64()) as u64;
+            self.bytes = 0;
+            self.start = Instant::now();
+        }
+    }
+}
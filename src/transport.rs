@@ -0,0 +1,136 @@
+//! What a request goes out as and what comes back, and the `Transport` that
+//! carries one to the other.
+//!
+//! `Http` hands every request to a `Transport`. The program's own is reqwest's
+//! async `Client`; the tests swap in one that takes every host's requests to a
+//! local server instead, so that the whole flow from a search to a packed
+//! chapter runs without the real site.
+
+use crate::{AppError, Result, REQUEST_TIMEOUT};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH},
+    Client, StatusCode, Url,
+};
+use std::{future::Future, io, pin::Pin};
+
+/// What the traits here return in place of an `async fn`, which a trait
+/// object cannot have.
+pub(crate) type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Carries a `Request` to its server and brings back the answer, following
+/// redirects on the way. Any status comes back as a `Reply`; only a request
+/// that got no answer at all is an error.
+pub(crate) trait Transport: Send + Sync {
+    fn send<'a>(&'a self, request: &'a Request) -> BoxFuture<'a, Result<Reply>>;
+}
+
+/// A response body, a piece at a time.
+pub(crate) trait Body: Send {
+    /// The next piece, or `None` once the body has ended. A body that breaks
+    /// off is an `io::Error`, as a short read from a file would be.
+    fn chunk(&mut self) -> BoxFuture<'_, Result<Option<Vec<u8>>>>;
+}
+
+/// `REQUEST_TIMEOUT` is applied to the wait for the headers and to each
+/// piece of the body separately, not to the whole exchange: a page read
+/// slowly under `--limit-rate` must not run out of time, but a connection
+/// that stalls must.
+impl Transport for Client {
+    fn send<'a>(&'a self, request: &'a Request) -> BoxFuture<'a, Result<Reply>> {
+        Box::pin(async move {
+            let sent = self.get(request.url.clone()).headers(request.headers.clone()).send();
+            let resp = tokio::time::timeout(REQUEST_TIMEOUT, sent).await.map_err(|_| timed_out())??;
+            Ok(Reply {
+                url: resp.url().clone(),
+                status: resp.status(),
+                // reqwest drops `content-length` along with `content-encoding`
+                // when it decompresses a body, so what is left describes the
+                // body as it is read.
+                headers: resp.headers().clone(),
+                body: Box::new(resp),
+            })
+        })
+    }
+}
+
+impl Body for reqwest::Response {
+    fn chunk(&mut self) -> BoxFuture<'_, Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            let chunk = tokio::time::timeout(REQUEST_TIMEOUT, reqwest::Response::chunk(self))
+                .await
+                .map_err(|_| timed_out())?
+                .map_err(io::Error::other)?;
+            Ok(chunk.map(|bytes| bytes.to_vec()))
+        })
+    }
+}
+
+fn timed_out() -> AppError {
+    AppError::Io(io::Error::new(io::ErrorKind::TimedOut, "no answer from the server in time"))
+}
+
+/// A GET, which is the only kind of request made. It has no body, so it can
+/// be sent again as it is, as `Throttle::send` does after a throttling answer.
+#[derive(Clone, Debug)]
+pub(crate) struct Request {
+    pub(crate) url: Url,
+    pub(crate) headers: HeaderMap,
+}
+
+impl Request {
+    pub(crate) fn get(url: &Url) -> Self {
+        Request { url: url.clone(), headers: HeaderMap::new() }
+    }
+
+    /// Set header `name`. The values sent are fixed text, URLs and cookies,
+    /// none of which can hold what a header may not.
+    pub(crate) fn header<V>(mut self, name: &'static str, value: V) -> Self
+    where
+        V: TryInto<HeaderValue>,
+        V::Error: std::fmt::Debug,
+    {
+        let value = value.try_into().expect("header values are visible ASCII");
+        self.headers.insert(HeaderName::from_static(name), value);
+        self
+    }
+
+    /// Append `pairs` to the URL's query string.
+    pub(crate) fn query(mut self, pairs: &[(&str, &str)]) -> Self {
+        self.url.query_pairs_mut().extend_pairs(pairs);
+        self
+    }
+}
+
+/// A server's answer: its status and headers, and the body left to read.
+pub(crate) struct Reply {
+    /// Where the answer came from, after any redirects.
+    pub(crate) url: Url,
+    pub(crate) status: StatusCode,
+    pub(crate) headers: HeaderMap,
+    pub(crate) body: Box<dyn Body>,
+}
+
+impl Reply {
+    /// The body's length as the server announced it, if it did.
+    pub(crate) fn content_length(&self) -> Option<u64> {
+        self.headers.get(CONTENT_LENGTH)?.to_str().ok()?.parse().ok()
+    }
+
+    /// `self`, unless the status is a client or server error.
+    pub(crate) fn error_for_status(self) -> Result<Self> {
+        if self.status.is_client_error() || self.status.is_server_error() {
+            return Err(AppError::Status(self.status, self.url));
+        }
+        Ok(self)
+    }
+
+    /// The whole body as text. The site's pages are UTF-8; a byte that is not
+    /// is replaced rather than failing the page.
+    pub(crate) async fn text(mut self) -> Result<String> {
+        let mut bytes = Vec::new();
+        while let Some(chunk) = self.body.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
@@ -0,0 +1,589 @@
+//! The full-screen pickers and download view, drawn with crossterm on the
+//! alternate screen.
+//!
+//! Used in place of the line prompts when both stdin and stdout are terminals.
+//! Each screen here has a line-prompt counterpart in `main.rs`, which stays the
+//! fallback for everything else — a redirected stdin, a pipe, `--no-tui`.
+
+use crate::{download_each, finish_run, logging, parse_chapter_pattern, AppError, Comic, Result, SearchResult};
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, ClearType},
+};
+use indicatif::ProgressBar;
+use std::{
+    io::{self, IsTerminal, Write},
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+use unicode_width::UnicodeWidthChar;
+
+/// How often the download view redraws, and so how long it takes to notice a
+/// key press.
+const REFRESH: Duration = Duration::from_millis(100);
+
+/// Whether there is a terminal on both ends to run the full-screen UI on.
+pub(crate) fn available() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal()
+}
+
+/// One line of a screen. `highlight` draws it in reverse video, which is how
+/// the cursor is shown.
+struct Line {
+    text: String,
+    highlight: bool,
+}
+
+impl Line {
+    fn plain(text: impl Into<String>) -> Self {
+        Line { text: text.into(), highlight: false }
+    }
+}
+
+/// Cut `text` to at most `width` terminal columns. CJK titles take two
+/// columns per character, so counting `char`s would run lines off the edge,
+/// where the terminal wraps them and pushes everything below down a row.
+fn fit(text: &str, width: usize) -> String {
+    let mut used = 0;
+    let mut out = String::new();
+    for c in text.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w > width {
+            break;
+        }
+        used += w;
+        out.push(c);
+    }
+    out
+}
+
+/// The rows of a `len`-row list to show in a `height`-row window, scrolled no
+/// further than needed to keep `cursor` in view. `top` is the previous first
+/// row and is updated in place, so that moving the cursor inside the window
+/// does not scroll it.
+pub(crate) fn scroll_window(top: &mut usize, cursor: usize, len: usize, height: usize) -> Range<usize> {
+    let height = height.max(1);
+    if cursor < *top {
+        *top = cursor;
+    } else if cursor >= *top + height {
+        *top = cursor + 1 - height;
+    }
+    *top = (*top).min(len.saturating_sub(height));
+    *top..(*top + height).min(len)
+}
+
+/// Raw mode on the alternate screen, for as long as this lives. Dropping it
+/// puts the terminal back the way it was, on every exit path — an error
+/// return or a panic included — which is what keeps a failure from leaving
+/// the user's shell without echo.
+struct Screen {
+    out: io::Stdout,
+    /// Lines logged while the screen is up, printed once it is gone.
+    _held: logging::Held,
+}
+
+impl Screen {
+    fn enter() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        // Built before anything else can fail, so that a failure below still
+        // restores the terminal on the way out.
+        let mut screen = Screen { out: io::stdout(), _held: logging::hold() };
+        queue!(screen.out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        screen.out.flush()?;
+        Ok(screen)
+    }
+
+    fn size() -> (usize, usize) {
+        let (w, h) = terminal::size().unwrap_or((80, 24));
+        (w as usize, h as usize)
+    }
+
+    /// Redraw the whole screen: `lines` from the top, `footer` on the last row.
+    fn draw(&mut self, lines: &[Line], footer: &str) -> Result<()> {
+        let (width, height) = Self::size();
+        queue!(self.out, terminal::Clear(ClearType::All))?;
+        for (y, line) in lines.iter().take(height.saturating_sub(1)).enumerate() {
+            queue!(self.out, cursor::MoveTo(0, y as u16))?;
+            if line.highlight {
+                queue!(self.out, SetAttribute(Attribute::Reverse))?;
+            }
+            queue!(self.out, Print(fit(&line.text, width)), SetAttribute(Attribute::Reset))?;
+        }
+        queue!(
+            self.out,
+            cursor::MoveTo(0, height.saturating_sub(1) as u16),
+            SetAttribute(Attribute::Dim),
+            Print(fit(footer, width)),
+            SetAttribute(Attribute::Reset)
+        )?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = queue!(self.out, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = self.out.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Wait for the next key press. `None` means the terminal was resized and the
+/// screen needs a redraw. Raw mode swallows Ctrl+C instead of raising SIGINT,
+/// so it is turned into `AppError::Interrupted` here, as `prompt_for_next_page`
+/// does.
+fn read_key() -> Result<Option<KeyEvent>> {
+    loop {
+        match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => {
+                if key.modifiers.contains(KeyModifiers::CONTROL)
+                    && matches!(key.code, KeyCode::Char('c' | 'C'))
+                {
+                    return Err(AppError::Interrupted);
+                }
+                return Ok(Some(key));
+            }
+            Event::Resize(..) => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+/// Move `cursor` within `0..len` for a navigation key. Returns whether the key
+/// was one.
+fn navigate(key: &KeyEvent, cursor: &mut usize, len: usize, page: usize) -> bool {
+    let last = len.saturating_sub(1);
+    *cursor = match key.code {
+        KeyCode::Up | KeyCode::Char('k') => cursor.saturating_sub(1),
+        KeyCode::Down | KeyCode::Char('j') => (*cursor + 1).min(last),
+        KeyCode::PageUp => cursor.saturating_sub(page),
+        KeyCode::PageDown => (*cursor + page).min(last),
+        KeyCode::Home | KeyCode::Char('g') => 0,
+        KeyCode::End | KeyCode::Char('G') => last,
+        _ => return false,
+    };
+    true
+}
+
+/// What the user did with the result list.
+pub(crate) enum ResultChoice {
+    Picked(usize),
+    /// Fetch the next page and show the list again.
+    MorePages,
+    /// Left with `q` or Esc, without a comic.
+    Cancelled,
+}
+
+/// The scrollable result list of a search or browse listing, for as long as
+/// pages keep being added to it.
+pub(crate) struct ResultPicker {
+    screen: Screen,
+    title: String,
+    cursor: usize,
+    top: usize,
+}
+
+impl ResultPicker {
+    pub(crate) fn new(title: String) -> Result<Self> {
+        Ok(ResultPicker { screen: Screen::enter()?, title, cursor: 0, top: 0 })
+    }
+
+    /// Show `results` and wait for a choice. `MorePages` is only offered when
+    /// `has_next` says the pager has another page. Leaving with `q` or Esc is
+    /// `Cancelled`; only Ctrl+C is `AppError::Interrupted`.
+    pub(crate) fn choose(&mut self, results: &[SearchResult], has_next: bool) -> Result<ResultChoice> {
+        loop {
+            let (_, height) = Screen::size();
+            // Title, blank line, detail line and footer around the list.
+            let list_height = height.saturating_sub(4).max(1);
+            let mut lines = vec![Line::plain(&self.title), Line::plain("")];
+            for i in scroll_window(&mut self.top, self.cursor, results.len(), list_height) {
+                let summary = results[i].describe();
+                lines.push(Line {
+                    text: format!("{:>4}. {}", i + 1, summary.lines().next().unwrap_or_default()),
+                    highlight: i == self.cursor,
+                });
+            }
+            if let Some(current) = results.get(self.cursor) {
+                let mut detail = Vec::new();
+                if !current.aliases.is_empty() {
+                    detail.push(format!("a.k.a. {}", current.aliases.join(", ")));
+                }
+                detail.push(format!("ID {}", current.comic_id));
+                while lines.len() < height.saturating_sub(2) {
+                    lines.push(Line::plain(""));
+                }
+                lines.push(Line::plain(detail.join("  ·  ")));
+            }
+            let footer = if has_next {
+                "↑↓ move  Enter select  n next page  q quit"
+            } else {
+                "↑↓ move  Enter select  q quit"
+            };
+            self.screen.draw(&lines, footer)?;
+
+            let Some(key) = read_key()? else { continue };
+            if navigate(&key, &mut self.cursor, results.len(), list_height) {
+                continue;
+            }
+            match key.code {
+                KeyCode::Enter if !results.is_empty() => return Ok(ResultChoice::Picked(self.cursor)),
+                KeyCode::Char('n' | ' ') if has_next => {
+                    self.screen.draw(&lines, "Loading the next page…")?;
+                    return Ok(ResultChoice::MorePages);
+                }
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(ResultChoice::Cancelled),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The chapter checkboxes behind the chapter picker, kept apart from the
+/// drawing so that the selection rules can be tested without a terminal.
+pub(crate) struct ChapterSelection {
+    selected: Vec<bool>,
+    downloaded: Vec<bool>,
+    /// The chapter last toggled, where a range selection starts.
+    anchor: Option<usize>,
+}
+
+impl ChapterSelection {
+    pub(crate) fn new(downloaded: Vec<bool>) -> Self {
+        ChapterSelection { selected: vec![false; downloaded.len()], downloaded, anchor: None }
+    }
+
+    pub(crate) fn toggle(&mut self, i: usize) {
+        self.selected[i] = !self.selected[i];
+        self.anchor = Some(i);
+    }
+
+    /// Select every chapter between the anchor and `i`, both included. Without
+    /// an anchor this selects `i` alone and makes it the anchor.
+    pub(crate) fn select_range(&mut self, i: usize) {
+        let from = self.anchor.unwrap_or(i);
+        let (lo, hi) = if from <= i { (from, i) } else { (i, from) };
+        self.selected[lo..=hi].fill(true);
+        self.anchor = Some(i);
+    }
+
+    pub(crate) fn select_all(&mut self) {
+        self.selected.fill(true);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.selected.fill(false);
+    }
+
+    /// Add the chapters at `indices` to the selection.
+    pub(crate) fn select_each(&mut self, indices: &[usize]) {
+        for &i in indices {
+            self.selected[i] = true;
+        }
+    }
+
+    /// Select exactly the chapters that have no archive yet.
+    pub(crate) fn select_missing(&mut self) {
+        for (sel, done) in self.selected.iter_mut().zip(&self.downloaded) {
+            *sel = !done;
+        }
+    }
+
+    pub(crate) fn is_selected(&self, i: usize) -> bool {
+        self.selected[i]
+    }
+
+    /// The selected chapters, in chapter order — the shape
+    /// `parse_chapter_selection` produces.
+    pub(crate) fn indices(&self) -> Vec<usize> {
+        (0..self.selected.len()).filter(|&i| self.selected[i]).collect()
+    }
+}
+
+/// A row of the chapter picker: a group heading or a chapter.
+enum ChapterRow {
+    Group(usize),
+    Chapter(usize),
+}
+
+/// Pick chapters of `comic` with checkboxes, grouped under their section
+/// headings and with the chapters already downloaded marked. `/` adds the
+/// chapters whose names match a pattern, the way `/REGEX` does at the line
+/// prompt. Returns the selected indices in chapter order; `q` leaves with
+/// none.
+pub(crate) fn pick_chapters(comic: &Comic) -> Result<Vec<usize>> {
+    let count = comic.chapters.len();
+    let downloaded: Vec<bool> = (0..count).map(|i| comic.zip_path(i).exists()).collect();
+    let mut selection = ChapterSelection::new(downloaded.clone());
+
+    let mut rows = Vec::with_capacity(count + 8);
+    for (i, chapter) in comic.chapters.iter().enumerate() {
+        if i == 0 || chapter.group != comic.chapters[i - 1].group {
+            rows.push(ChapterRow::Group(i));
+        }
+        rows.push(ChapterRow::Chapter(i));
+    }
+    // Row of each chapter, so that the window can follow a cursor that moves
+    // over chapters only.
+    let chapter_row: Vec<usize> = rows
+        .iter()
+        .enumerate()
+        .filter_map(|(r, row)| matches!(row, ChapterRow::Chapter(_)).then_some(r))
+        .collect();
+
+    let mut screen = Screen::enter()?;
+    let mut cursor = 0;
+    let mut top = 0;
+    let mut message = String::new();
+    // The pattern being typed after `/`, while it is.
+    let mut pattern: Option<String> = None;
+    loop {
+        let (_, height) = Screen::size();
+        let list_height = height.saturating_sub(3).max(1);
+        let picked = selection.indices().len();
+        let mut lines = vec![
+            Line::plain(format!("{} — {} of {} chapters selected", comic.title, picked, count)),
+            Line::plain(""),
+        ];
+        for r in scroll_window(&mut top, chapter_row.get(cursor).copied().unwrap_or(0), rows.len(), list_height) {
+            lines.push(match rows[r] {
+                ChapterRow::Group(i) => Line::plain(format!("{}:", comic.chapters[i].group)),
+                ChapterRow::Chapter(i) => Line {
+                    text: format!(
+                        "  [{}] {:>4}: {}{}",
+                        if selection.is_selected(i) { 'x' } else { ' ' },
+                        i + 1,
+                        comic.chapters[i].name,
+                        if downloaded[i] { "  ✓ downloaded" } else { "" }
+                    ),
+                    highlight: i == cursor,
+                },
+            });
+        }
+        let typing;
+        let footer = if let Some(p) = &pattern {
+            typing = format!("/{p}_   Enter select matching names  Esc cancel");
+            typing.as_str()
+        } else if message.is_empty() {
+            "Space toggle  r range  a all  n none  m missing  / by name  Enter download  q quit"
+        } else {
+            message.as_str()
+        };
+        screen.draw(&lines, footer)?;
+        message.clear();
+
+        let Some(key) = read_key()? else { continue };
+        if let Some(p) = &mut pattern {
+            match key.code {
+                KeyCode::Char(c) => p.push(c),
+                KeyCode::Backspace => {
+                    p.pop();
+                }
+                KeyCode::Esc => pattern = None,
+                KeyCode::Enter => {
+                    message = match parse_chapter_pattern(&format!("/{p}"), &comic.chapters) {
+                        Some(matched) => {
+                            selection.select_each(&matched);
+                            format!("Selected {} chapter(s) matching /{p}/", matched.len())
+                        }
+                        None => format!("No chapter name matches /{p}/, or it is not a valid pattern"),
+                    };
+                    pattern = None;
+                }
+                _ => {}
+            }
+            continue;
+        }
+        // Shift+arrow extends the selection as it moves, where the terminal
+        // reports the modifier at all; `r` does the same from the anchor.
+        if key.modifiers.contains(KeyModifiers::SHIFT)
+            && matches!(key.code, KeyCode::Up | KeyCode::Down)
+        {
+            if selection.anchor.is_none() {
+                selection.toggle(cursor);
+            }
+            let plain = KeyEvent::new(key.code, KeyModifiers::NONE);
+            navigate(&plain, &mut cursor, count, list_height);
+            selection.select_range(cursor);
+            continue;
+        }
+        if navigate(&key, &mut cursor, count, list_height) {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char(' ') => selection.toggle(cursor),
+            KeyCode::Char('r') => selection.select_range(cursor),
+            KeyCode::Char('a') => selection.select_all(),
+            KeyCode::Char('n') => selection.clear(),
+            KeyCode::Char('m') => selection.select_missing(),
+            KeyCode::Char('/') => pattern = Some(String::new()),
+            KeyCode::Enter => {
+                let indices = selection.indices();
+                if indices.is_empty() {
+                    message = "Nothing selected — Space selects the chapter under the cursor".to_string();
+                } else {
+                    return Ok(indices);
+                }
+            }
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(Vec::new()),
+            _ => {}
+        }
+    }
+}
+
+/// Where one chapter of the download view stands.
+enum RowState {
+    Queued,
+    Active,
+    Done,
+    Skipped,
+    Failed(String),
+}
+
+struct DownloadRow {
+    index: usize,
+    state: RowState,
+    /// Hidden: the view draws it, indicatif does not. `download_chapter` only
+    /// ever sees a bar, so it advances this one exactly as it would a visible
+    /// one.
+    bar: ProgressBar,
+}
+
+/// Download `indices` with a live full-screen view of every chapter's
+/// progress, then print what happened to each one and the run's summary on
+/// the normal screen, where they stay in the scrollback. `q` or Ctrl+C stops
+/// after the chapter in progress.
+pub(crate) fn download_view(comic: &Comic, indices: &[usize]) -> Result<()> {
+    let rows = Mutex::new(
+        indices
+            .iter()
+            .map(|&index| DownloadRow { index, state: RowState::Queued, bar: ProgressBar::hidden() })
+            .collect::<Vec<_>>(),
+    );
+    let finished = AtomicBool::new(false);
+    let stop = AtomicBool::new(false);
+
+    let (drawn, summary) = thread::scope(|scope| {
+        let view = scope.spawn(|| -> Result<()> {
+            let mut screen = Screen::enter()?;
+            let mut top = 0;
+            while !finished.load(Ordering::Relaxed) {
+                draw_downloads(&mut screen, comic, &rows.lock().unwrap(), &mut top, stop.load(Ordering::Relaxed))?;
+                if event::poll(REFRESH)?
+                    && let Event::Key(KeyEvent { code, modifiers, kind: KeyEventKind::Press, .. }) = event::read()?
+                {
+                    let ctrl_c = modifiers.contains(KeyModifiers::CONTROL)
+                        && matches!(code, KeyCode::Char('c' | 'C'));
+                    if ctrl_c || matches!(code, KeyCode::Char('q') | KeyCode::Esc) {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                }
+            }
+            Ok(())
+        });
+
+        let summary = download_each(
+            comic,
+            indices,
+            |idx| {
+                let mut rows = rows.lock().unwrap();
+                let row = rows.iter_mut().find(|r| r.index == idx).expect("every index has a row");
+                row.state = RowState::Active;
+                row.bar.clone()
+            },
+            |idx, result| {
+                let mut rows = rows.lock().unwrap();
+                let row = rows.iter_mut().find(|r| r.index == idx).expect("every index has a row");
+                row.state = match result {
+                    Ok(true) => RowState::Done,
+                    Ok(false) => RowState::Skipped,
+                    Err(e) => RowState::Failed(e.to_string()),
+                };
+                !stop.load(Ordering::Relaxed)
+            },
+        );
+        finished.store(true, Ordering::Relaxed);
+        (view.join().expect("download view panicked"), summary)
+    });
+
+    // Back on the normal screen: leave a record of the run behind.
+    for row in rows.into_inner().unwrap() {
+        let name = &comic.chapters[row.index].name;
+        match row.state {
+            RowState::Done => println!("Downloaded {}: {}", row.index + 1, name),
+            RowState::Skipped => println!("Skipped    {}: {} (already downloaded)", row.index + 1, name),
+            RowState::Failed(e) => log::error!("Failed     {}: {}: {}", row.index + 1, name, e),
+            RowState::Queued | RowState::Active => println!("Not run    {}: {}", row.index + 1, name),
+        }
+    }
+    let verdict = finish_run(comic, &summary);
+    if stop.load(Ordering::Relaxed) {
+        return Err(AppError::Interrupted);
+    }
+    drawn?;
+    verdict
+}
+
+fn draw_downloads(
+    screen: &mut Screen,
+    comic: &Comic,
+    rows: &[DownloadRow],
+    top: &mut usize,
+    stopping: bool,
+) -> Result<()> {
+    let (width, height) = Screen::size();
+    let list_height = height.saturating_sub(3).max(1);
+    let done = rows
+        .iter()
+        .filter(|r| !matches!(r.state, RowState::Queued | RowState::Active))
+        .count();
+    let mut lines = vec![
+        Line::plain(format!("{} — {} of {} chapters finished", comic.title, done, rows.len())),
+        Line::plain(""),
+    ];
+    let active = rows.iter().position(|r| matches!(r.state, RowState::Active)).unwrap_or(done);
+    // The bar gets a third of the screen; the name gets what is left.
+    let bar_width = (width / 3).clamp(10, 40);
+    for row in &rows[scroll_window(top, active, rows.len(), list_height)] {
+        let (pos, len) = (row.bar.position(), row.bar.length().unwrap_or(0));
+        let status = match &row.state {
+            RowState::Queued => "queued".to_string(),
+            RowState::Active if len == 0 => "starting".to_string(),
+            RowState::Active => format!("{pos}/{len}  {}", row.bar.prefix()),
+            RowState::Done => "done".to_string(),
+            RowState::Skipped => "skipped".to_string(),
+            RowState::Failed(e) => format!("failed: {e}"),
+        };
+        let filled = (pos * bar_width as u64).checked_div(len).unwrap_or(0) as usize;
+        let filled = match row.state {
+            RowState::Done | RowState::Skipped => bar_width,
+            _ => filled,
+        };
+        lines.push(Line {
+            text: format!(
+                "[{}{}] {:>4}: {}  {}",
+                "#".repeat(filled),
+                "-".repeat(bar_width - filled),
+                row.index + 1,
+                comic.chapters[row.index].name,
+                status
+            ),
+            highlight: matches!(row.state, RowState::Active),
+        });
+    }
+    let footer = if stopping {
+        "Stopping after the current chapter…"
+    } else {
+        "q stop after the current chapter"
+    };
+    screen.draw(&lines, footer)
+}
@@ -0,0 +1,274 @@
+//! Structural checks on a downloaded page, run on the whole body before it is
+//! written under its final name.
+//!
+//! `looks_like_image` only reads a response's first bytes, and a `Content-Length`
+//! check needs the server to send one. Neither notices a JPEG cut off before
+//! its end marker or a WebP shorter than its own header says — and a page that
+//! gets past them is skipped as finished by every later run. The checks here
+//! walk the container far enough to see that it is complete, without decoding
+//! the picture; `--decode-pages` adds a full decode on top.
+
+use crate::PageFormat;
+
+/// Pages narrower or shorter than this are not pages: they are the tracking
+/// pixels and placeholder images a hotlink guard answers with.
+const MIN_PAGE_SIDE: u32 = 16;
+
+/// More pixels than any scanned page has. A header claiming more is corrupt,
+/// and would make a full decode try to allocate gigabytes.
+const MAX_PAGE_PIXELS: u64 = 200_000_000;
+
+/// Check that `data` is a complete, sane image in one of the `PageFormat`s,
+/// and decode it in full too with `decode` set — for the formats the image
+/// crate can decode. The error says what is wrong, for the caller to put in
+/// context.
+pub(crate) fn check_image(data: &[u8], decode: bool) -> Result<(u32, u32), String> {
+    let Some(format) = PageFormat::sniff(data) else {
+        return Err("not an image in a format pages come in".to_string());
+    };
+    let (width, height) = match format {
+        PageFormat::Jpeg => check_jpeg(data)?,
+        PageFormat::Png => check_png(data)?,
+        PageFormat::Gif => check_gif(data)?,
+        PageFormat::WebP => check_webp(data)?,
+        PageFormat::Avif => check_avif(data)?,
+    };
+    if width < MIN_PAGE_SIDE || height < MIN_PAGE_SIDE {
+        return Err(format!("{width}x{height} is too small to be a page"));
+    }
+    if width as u64 * height as u64 > MAX_PAGE_PIXELS {
+        return Err(format!("{width}x{height} is too large to be a page"));
+    }
+    if decode && format.decodes() {
+        image::load_from_memory(data).map_err(|e| format!("does not decode: {e}"))?;
+    }
+    Ok((width, height))
+}
+
+fn u16_be(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_be(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u64_be(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+fn u16_le(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_le(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u24_le(data: &[u8], at: usize) -> Option<u32> {
+    let b = data.get(at..at + 3)?;
+    Some(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16)
+}
+
+/// Walk a JPEG's segments from SOI to EOI, returning the frame's dimensions.
+/// Bytes after EOI are allowed: some encoders leave padding there.
+fn check_jpeg(data: &[u8]) -> Result<(u32, u32), String> {
+    let truncated = || "JPEG ends before its EOI marker".to_string();
+    let mut pos = 2;
+    let mut size = None;
+    loop {
+        if data.get(pos) != Some(&0xFF) {
+            return match data.get(pos) {
+                None => Err(truncated()),
+                Some(_) => Err(format!("JPEG has no marker where one belongs, at byte {pos}")),
+            };
+        }
+        // A marker may be preceded by any number of 0xFF fill bytes.
+        while data.get(pos) == Some(&0xFF) {
+            pos += 1;
+        }
+        let marker = *data.get(pos).ok_or_else(truncated)?;
+        pos += 1;
+        match marker {
+            0xD9 => break,
+            // RSTn and TEM stand alone, without a length.
+            0xD0..=0xD7 | 0x01 => continue,
+            _ => {}
+        }
+        let len = u16_be(data, pos).ok_or_else(truncated)? as usize;
+        if len < 2 {
+            return Err(format!("JPEG segment at byte {pos} has an impossible length {len}"));
+        }
+        let end = pos + len;
+        if end > data.len() {
+            return Err(truncated());
+        }
+        // SOF0-SOF15, less DHT, JPG and DAC, which share the range.
+        if matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+            let height = u16_be(data, pos + 3).ok_or_else(truncated)?;
+            let width = u16_be(data, pos + 5).ok_or_else(truncated)?;
+            size = Some((width as u32, height as u32));
+        }
+        pos = end;
+        if marker == 0xDA {
+            // Entropy-coded data runs up to the next marker. Inside it, 0xFF is
+            // only ever followed by a stuffed 0x00, a restart marker or fill.
+            loop {
+                let ff = pos + data[pos..].iter().position(|&b| b == 0xFF).ok_or_else(truncated)?;
+                match data.get(ff + 1) {
+                    None => return Err(truncated()),
+                    Some(0x00 | 0xD0..=0xD7 | 0xFF) => pos = ff + 1,
+                    Some(_) => {
+                        pos = ff;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    size.ok_or_else(|| "JPEG has no frame header".to_string())
+}
+
+/// Walk a PNG's chunks from the signature to IEND, returning the dimensions
+/// its IHDR gives.
+fn check_png(data: &[u8]) -> Result<(u32, u32), String> {
+    let truncated = || "PNG ends before its IEND chunk".to_string();
+    let mut pos = 8;
+    let mut size = None;
+    loop {
+        let len = u32_be(data, pos).ok_or_else(truncated)? as usize;
+        let kind = data.get(pos + 4..pos + 8).ok_or_else(truncated)?;
+        // Length, type, data and CRC.
+        let end = pos + 12 + len;
+        if end > data.len() {
+            return Err(truncated());
+        }
+        if pos == 8 {
+            if kind != b"IHDR" || len < 8 {
+                return Err("PNG does not start with an IHDR chunk".to_string());
+            }
+            size = Some((u32_be(data, pos + 8).unwrap(), u32_be(data, pos + 12).unwrap()));
+        }
+        if kind == b"IEND" {
+            return size.ok_or_else(truncated);
+        }
+        pos = end;
+    }
+}
+
+/// Walk a GIF's blocks from the header to the trailer, returning the
+/// dimensions of its logical screen.
+fn check_gif(data: &[u8]) -> Result<(u32, u32), String> {
+    let truncated = || "GIF ends before its trailer".to_string();
+    // A colour table follows when the flags' top bit is set; the low three
+    // bits give its size.
+    let color_table = |flags: u8| if flags & 0x80 != 0 { 3 << ((flags & 7) + 1) } else { 0 };
+    // Data sub-blocks run until one of length zero.
+    let skip_sub_blocks = |mut pos: usize| loop {
+        let len = *data.get(pos).ok_or_else(truncated)? as usize;
+        pos += 1 + len;
+        if len == 0 {
+            return Ok::<_, String>(pos);
+        }
+    };
+    let width = u16_le(data, 6).ok_or_else(truncated)?;
+    let height = u16_le(data, 8).ok_or_else(truncated)?;
+    let mut pos = 13 + color_table(*data.get(10).ok_or_else(truncated)?);
+    loop {
+        match data.get(pos) {
+            None => return Err(truncated()),
+            Some(0x3B) => return Ok((width as u32, height as u32)),
+            // Extension: introducer, label, sub-blocks.
+            Some(0x21) => pos = skip_sub_blocks(pos + 2)?,
+            // Image: descriptor, colour table, LZW code size, sub-blocks.
+            Some(0x2C) => {
+                let flags = *data.get(pos + 9).ok_or_else(truncated)?;
+                pos = skip_sub_blocks(pos + 10 + color_table(flags) + 1)?;
+            }
+            Some(b) => return Err(format!("GIF has an unknown block 0x{b:02x} at byte {pos}")),
+        }
+    }
+}
+
+/// Check that an AVIF's top-level boxes add up to the file, returning the
+/// dimensions its `ispe` property gives.
+fn check_avif(data: &[u8]) -> Result<(u32, u32), String> {
+    let mut pos = 0;
+    let mut size = None;
+    while pos < data.len() {
+        let short = "AVIF box header is cut short";
+        // The size counts the header too, which a 64-bit size makes 16 bytes
+        // long rather than 8.
+        let (len, header) = match u32_be(data, pos).ok_or(short)? {
+            // To the end of the file.
+            0 => ((data.len() - pos) as u64, 8),
+            1 => (u64_be(data, pos + 8).ok_or(short)?, 16),
+            len => (len as u64, 8),
+        };
+        let kind = data.get(pos + 4..pos + 8).ok_or(short)?;
+        if len < header {
+            return Err("AVIF box is shorter than its own header".to_string());
+        }
+        // A 64-bit size is taken from the file as it stands, and a damaged
+        // one may be anything up to `u64::MAX`.
+        if (pos as u64).checked_add(len).is_none_or(|end| end > data.len() as u64) {
+            return Err("AVIF box runs past the end of the file".to_string());
+        }
+        let end = pos + len as usize;
+        if kind == b"meta" {
+            // The item properties nest several boxes deep; the image spatial
+            // extents box is distinctive enough to find by its tag. Its
+            // version and flags come before the size.
+            let meta = &data[pos..end];
+            size = meta
+                .windows(4)
+                .position(|tag| tag == b"ispe")
+                .and_then(|at| Some((u32_be(meta, at + 8)?, u32_be(meta, at + 12)?)));
+        }
+        pos = end;
+    }
+    size.ok_or_else(|| "AVIF has no image size property".to_string())
+}
+
+/// Check a WebP's RIFF size against the file and its chunks against the RIFF
+/// size, returning the dimensions its first chunk gives.
+fn check_webp(data: &[u8]) -> Result<(u32, u32), String> {
+    let riff = u32_le(data, 4).ok_or("WebP header is cut short")? as usize + 8;
+    if riff != data.len() {
+        return Err(format!("WebP header says {riff} bytes, but there are {}", data.len()));
+    }
+    let mut pos = 12;
+    while pos < data.len() {
+        let size = u32_le(data, pos + 4).ok_or("WebP chunk header is cut short")? as usize;
+        // Chunks are padded to an even length.
+        pos += 8 + size + (size & 1);
+        if pos > data.len() {
+            return Err("WebP chunk runs past the end of the file".to_string());
+        }
+    }
+    let payload = &data[20.min(data.len())..];
+    let bad = |what: &str| format!("WebP {what} chunk is malformed");
+    match data.get(12..16) {
+        Some(b"VP8 ") => {
+            if payload.get(3..6) != Some(&[0x9D, 0x01, 0x2A][..]) {
+                return Err(bad("VP8"));
+            }
+            let width = u16_le(payload, 6).ok_or_else(|| bad("VP8"))? & 0x3FFF;
+            let height = u16_le(payload, 8).ok_or_else(|| bad("VP8"))? & 0x3FFF;
+            Ok((width as u32, height as u32))
+        }
+        Some(b"VP8L") => {
+            if payload.first() != Some(&0x2F) {
+                return Err(bad("VP8L"));
+            }
+            let bits = u32_le(payload, 1).ok_or_else(|| bad("VP8L"))?;
+            Ok(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1))
+        }
+        Some(b"VP8X") => {
+            let width = u24_le(payload, 4).ok_or_else(|| bad("VP8X"))? + 1;
+            let height = u24_le(payload, 7).ok_or_else(|| bad("VP8X"))? + 1;
+            Ok((width, height))
+        }
+        _ => Err("WebP has no image chunk".to_string()),
+    }
+}